@@ -0,0 +1,314 @@
+use glam::{Mat4, Vec2, Vec3};
+
+use crate::culling::Aabb;
+use crate::districts::{district_at, DistrictStyle};
+use crate::terrain::height_at;
+use crate::world::{hash, lot_ground, BUILDING_WIDTH, GRID_SPACING};
+
+// Buildings of footprint 1 spread over this much of their lot
+const BASE_LOT_WIDTH: f32 = 8.0;
+// Widest anything spreads over its lot, leaving an alley to the next one
+pub const LOT_WIDTH: f32 = 10.0;
+// Buildings at least this tall may carry a spire or an antenna
+const CROWN_MIN_HEIGHT: f32 = 38.0;
+const SPIRE_COLOR: Vec3 = Vec3::new(0.62, 0.64, 0.66);
+// Parks: a lawn slab with a few conifers on it
+pub const LAWN_HEIGHT: f32 = 0.3;
+pub const LAWN_COLOR: Vec3 = Vec3::new(0.36, 0.50, 0.27);
+const TREE_COLOR: Vec3 = Vec3::new(0.20, 0.36, 0.20);
+const TRUNK_COLOR: Vec3 = Vec3::new(0.36, 0.27, 0.20);
+// Bridges: a concrete deck carrying the road, on slim piers down to the bed
+const DECK_WIDTH: f32 = 8.0;
+const DECK_THICKNESS: f32 = 1.5;
+const PIER_WIDTH: f32 = 1.2;
+const BRIDGE_COLOR: Vec3 = Vec3::new(0.58, 0.57, 0.55);
+// Approaches step down from the deck to the bank in this many blocks
+const RAMP_STEPS: usize = 4;
+
+// The unit meshes parts are scaled from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shape {
+    Box,
+    Cylinder,
+    Cone,
+}
+
+impl Shape {
+    pub const ALL: [Shape; 3] = [Shape::Box, Shape::Cylinder, Shape::Cone];
+}
+
+// Window pattern painted on a part's walls; the number is what
+// SCENE_FRAGMENT_SHADER switches on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Facade {
+    Plain = 0,
+    Office = 1,
+    // Floor-to-ceiling glass
+    Curtain = 2,
+    Residential = 3,
+    // A ribbon of windows under each roof line
+    Industrial = 4,
+    // Tall warehouse windows
+    Loft = 5,
+    // The ground: no windows, coloured by slope and height (see terrain.rs)
+    Terrain = 6,
+}
+
+// One solid piece of a building: its shape's unit mesh stretched to fill
+// `bounds`
+#[derive(Clone, Copy, Debug)]
+pub struct Part {
+    pub shape: Shape,
+    pub bounds: Aabb,
+    pub color: Vec3,
+    pub facade: Facade,
+}
+
+impl Part {
+    // Takes any two opposite corners, relative to the foot of the lot
+    fn new(shape: Shape, foot: Vec3, a: Vec3, b: Vec3, color: Vec3, facade: Facade) -> Self {
+        Self { shape, bounds: Aabb { min: foot + a.min(b), max: foot + a.max(b) }, color, facade }
+    }
+
+    pub fn model_matrix(&self) -> Mat4 {
+        Mat4::from_translation(self.bounds.center()) * Mat4::from_scale(self.bounds.max - self.bounds.min)
+    }
+
+    pub fn contains_point(&self, p: Vec3) -> bool {
+        if !self.bounds.contains_point(p) {
+            return false;
+        }
+        let size = self.bounds.max - self.bounds.min;
+        let center = self.bounds.center();
+        // 0 on the axis, 1 at the rim
+        let radial = Vec2::new((p.x - center.x) / size.x, (p.z - center.z) / size.z).length() * 2.0;
+        match self.shape {
+            Shape::Box => true,
+            Shape::Cylinder => radial <= 1.0,
+            Shape::Cone => radial <= 1.0 - (p.y - self.bounds.min.y) / size.y,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Archetype {
+    Block,
+    // Tiers that narrow towards the top
+    Setback,
+    // Two slim towers joined by a skybridge
+    TwinTowers,
+    // A tower on a wide low base
+    PodiumTower,
+    Cylinder,
+    LShape,
+    // A shed filling its lot, with plant on the roof
+    Warehouse,
+}
+
+impl Archetype {
+    // One of the district's archetypes, by its odds
+    pub fn pick(hash: u64, style: &DistrictStyle) -> Self {
+        let total: u32 = style.archetypes.iter().map(|&(_, odds)| odds).sum();
+        let mut roll = ((hash >> 24) % total as u64) as u32;
+        for &(archetype, odds) in style.archetypes {
+            if roll < odds {
+                return archetype;
+            }
+            roll -= odds;
+        }
+        unreachable!()
+    }
+}
+
+// The parts of the building in cell (x, z), `height` tall to its main roof
+// from the lot's ground, all within LOT_WIDTH of the cell centre and styled
+// by its district
+pub fn building_parts(x: i32, z: i32, height: f32, color: Vec3) -> Vec<Part> {
+    let h = hash(x, z);
+    let style = district_at(x, z).style();
+    // Variations within an archetype come from bits the other choices don't use
+    let bit = |n: u32| (h >> n) & 1 == 1;
+    let foot = Vec3::new(x as f32 * GRID_SPACING, lot_ground(x, z), z as f32 * GRID_SPACING);
+    let (w, l) = (BUILDING_WIDTH * 0.5 * style.footprint, BASE_LOT_WIDTH * 0.5 * style.footprint);
+    let stone = color.lerp(Vec3::new(0.72, 0.69, 0.63), 0.6);
+    let part = |shape: Shape, a: Vec3, b: Vec3, color: Vec3| Part::new(shape, foot, a, b, color, style.facade);
+    let block = |a: Vec3, b: Vec3, color: Vec3| part(Shape::Box, a, b, color);
+
+    let mut parts = match Archetype::pick(h, style) {
+        Archetype::Block => vec![block(Vec3::new(-w, 0.0, -w), Vec3::new(w, height, w), color)],
+        Archetype::Setback => {
+            let tiers: &[f32] = if bit(32) { &[0.0, 0.6, 1.0] } else { &[0.0, 0.45, 0.75, 1.0] };
+            let n = tiers.len() - 1;
+            (0..n).map(|i| {
+                let half = l - (l - w * 0.7) * i as f32 / (n - 1) as f32;
+                block(Vec3::new(-half, tiers[i] * height, -half), Vec3::new(half, tiers[i + 1] * height, half), color)
+            }).collect()
+        }
+        Archetype::TwinTowers => {
+            // Along x or z
+            let axis = if bit(32) { Vec3::X } else { Vec3::Z };
+            let across = Vec3::ONE - axis - Vec3::Y;
+            let tower = |side: f32, top: f32| block(axis * side + across * -w, axis * side * l + across * w + Vec3::Y * top, color);
+            let bridge_y = height * 0.55;
+            vec![
+                tower(1.0, height),
+                tower(-1.0, height * 0.85),
+                block(axis * -1.0 + across * -w * 0.3 + Vec3::Y * bridge_y, axis + across * w * 0.3 + Vec3::Y * (bridge_y + 2.0), stone),
+            ]
+        }
+        Archetype::PodiumTower => {
+            let podium = (height * 0.22).max(6.0).min(height * 0.4);
+            vec![
+                block(Vec3::new(-l, 0.0, -l), Vec3::new(l, podium, l), stone),
+                block(Vec3::new(-w, podium, -w), Vec3::new(w, height, w), color),
+            ]
+        }
+        Archetype::Cylinder => {
+            let (outer, inner) = (l * 0.75, l * 0.5);
+            vec![
+                part(Shape::Cylinder, Vec3::new(-outer, 0.0, -outer), Vec3::new(outer, height * 0.9, outer), color),
+                part(Shape::Cylinder, Vec3::new(-inner, height * 0.9, -inner), Vec3::new(inner, height, inner), stone),
+            ]
+        }
+        Archetype::LShape => {
+            // Mirrored into one of four orientations
+            let flip = Vec3::new(if bit(32) { 1.0 } else { -1.0 }, 1.0, if bit(33) { 1.0 } else { -1.0 });
+            let arm = l * 0.875;
+            vec![
+                block(Vec3::new(-l, 0.0, -l) * flip, Vec3::new(l, height, -l + arm) * flip, color),
+                block(Vec3::new(-l, 0.0, -l + arm) * flip, Vec3::new(-l + arm, height * 0.65, l) * flip, color),
+            ]
+        }
+        Archetype::Warehouse => {
+            let plant = Vec3::new(l * 0.3, 0.0, l * 0.3);
+            let roof = Vec3::Y * height;
+            vec![
+                block(Vec3::new(-l, 0.0, -l), Vec3::new(l, height, l), color),
+                Part::new(Shape::Box, foot, roof - plant, roof + plant + Vec3::Y * 2.5, SPIRE_COLOR, Facade::Plain),
+            ]
+        }
+    };
+
+    // A spire or antenna on top of the tallest part
+    if height >= CROWN_MIN_HEIGHT {
+        let roof = *parts.iter().max_by(|a, b| a.bounds.max.y.total_cmp(&b.bounds.max.y)).unwrap();
+        let center = roof.bounds.center();
+        let top = Vec3::new(center.x, roof.bounds.max.y, center.z) - foot;
+        let crown = match (h >> 40) % 100 {
+            0..=11 => Some((Shape::Cone, 1.25, height * 0.35)),
+            12..=31 => Some((Shape::Box, 0.15, height * 0.25)),
+            _ => None,
+        };
+        if let Some((shape, radius, tall)) = crown {
+            parts.push(Part::new(shape, foot, top - Vec3::new(radius, 0.0, radius), top + Vec3::new(radius, tall, radius), SPIRE_COLOR, Facade::Plain));
+        }
+    }
+    parts
+}
+
+// A park lot in cell (x, z): lawn over the whole lot and up to three trees
+pub fn park_parts(x: i32, z: i32) -> Vec<Part> {
+    let h = hash(x, z);
+    let foot = Vec3::new(x as f32 * GRID_SPACING, lot_ground(x, z), z as f32 * GRID_SPACING);
+    let l = LOT_WIDTH * 0.5;
+    let mut parts = vec![Part::new(Shape::Box, foot, Vec3::new(-l, 0.0, -l), Vec3::new(l, LAWN_HEIGHT, l), LAWN_COLOR, Facade::Plain)];
+    for i in 0..h % 4 {
+        // Four hash nibbles per tree, 0..1
+        let t = |n: u64| ((h >> (8 + 16 * i + 4 * n)) & 0xF) as f32 / 15.0;
+        let (radius, tall) = (1.2 + 0.6 * t(0), 4.0 + 3.0 * t(1));
+        let spot = Vec3::new(t(2) * 2.0 - 1.0, 0.0, t(3) * 2.0 - 1.0) * (l - radius) + Vec3::Y * LAWN_HEIGHT;
+        let trunk = Vec3::new(0.2, 0.0, 0.2);
+        parts.push(Part::new(Shape::Box, foot, spot - trunk, spot + trunk + Vec3::Y * 1.5, TRUNK_COLOR, Facade::Plain));
+        let crown = spot + Vec3::Y;
+        parts.push(Part::new(Shape::Cone, foot, crown - Vec3::new(radius, 0.0, radius), crown + Vec3::new(radius, tall, radius), TREE_COLOR, Facade::Plain));
+    }
+    parts
+}
+
+// The span of bridge in road cell (x, z), its deck top at `deck`, running
+// along x, z or both where two roads cross. Every third cell along the road
+// stands on a pier; the rest is open water beneath.
+pub fn bridge_parts(x: i32, z: i32, deck: f32, along_x: bool, along_z: bool) -> Vec<Part> {
+    let foot = Vec3::new(x as f32 * GRID_SPACING, deck, z as f32 * GRID_SPACING);
+    let (half, side) = (GRID_SPACING * 0.5, DECK_WIDTH * 0.5);
+    let slab = |ax: f32, az: f32| Part::new(Shape::Box, foot, Vec3::new(-ax, -DECK_THICKNESS, -az), Vec3::new(ax, 0.0, az), BRIDGE_COLOR, Facade::Plain);
+    let mut parts = Vec::new();
+    if along_x {
+        parts.push(slab(half, side));
+    }
+    if along_z {
+        parts.push(slab(side, half));
+    }
+    if (along_x && x.rem_euclid(3) == 0) || (along_z && z.rem_euclid(3) == 0) {
+        let bed = height_at(foot.x, foot.z);
+        let p = PIER_WIDTH * 0.5;
+        parts.push(Part::new(Shape::Box, foot, Vec3::new(-p, bed - deck, -p), Vec3::new(p, -DECK_THICKNESS, p), BRIDGE_COLOR, Facade::Plain));
+    }
+    parts
+}
+
+// The approach in shore cell (x, z) to each bridge deck beside it, one per
+// direction towards the water: an embankment stepping down from the deck at
+// the water's edge to the road's ground on the far side of the cell
+pub fn ramp_parts(x: i32, z: i32, deck: f32, towards: &[(i32, i32)]) -> Vec<Part> {
+    let foot = Vec3::new(x as f32 * GRID_SPACING, lot_ground(x, z), z as f32 * GRID_SPACING);
+    let (half, side) = (GRID_SPACING * 0.5, DECK_WIDTH * 0.5);
+    let rise = deck - foot.y;
+    let mut parts = Vec::new();
+    for &(dx, dz) in towards {
+        let (along, across) = (Vec3::new(dx as f32, 0.0, dz as f32), Vec3::new(dz as f32, 0.0, dx as f32));
+        for step in 0..RAMP_STEPS {
+            let (near, far) = (step as f32 / RAMP_STEPS as f32, (step + 1) as f32 / RAMP_STEPS as f32);
+            let top = rise * (1.0 - near);
+            let a = along * (half - near * GRID_SPACING) - across * side;
+            let b = along * (half - far * GRID_SPACING) + across * side + Vec3::Y * top;
+            parts.push(Part::new(Shape::Box, foot, a, b, BRIDGE_COLOR, Facade::Plain));
+        }
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::get_building_info;
+
+    fn buildings() -> impl Iterator<Item = (i32, i32, f32, Vec<Part>)> {
+        (-240..240).step_by(4).flat_map(|x| (-240..240).step_by(3).map(move |z| (x, z)))
+            .filter_map(|(x, z)| get_building_info(x, z).map(|(height, color)| (x, z, height, building_parts(x, z, height, color))))
+    }
+
+    #[test]
+    fn every_shape_fits_its_lot() {
+        let mut seen = Vec::new();
+        for (x, z, height, parts) in buildings() {
+            let archetype = Archetype::pick(hash(x, z), district_at(x, z).style());
+            if !seen.contains(&archetype) {
+                seen.push(archetype);
+            }
+            let ground = lot_ground(x, z);
+            let center = Vec3::new(x as f32 * GRID_SPACING, ground, z as f32 * GRID_SPACING);
+            for part in &parts {
+                let (min, max) = (part.bounds.min - center, part.bounds.max - center);
+                assert!(min.x >= -LOT_WIDTH * 0.5 && max.x <= LOT_WIDTH * 0.5 && min.z >= -LOT_WIDTH * 0.5 && max.z <= LOT_WIDTH * 0.5);
+                assert!(min.y >= 0.0 && max.y > min.y);
+            }
+            // Standing on the ground, reaching its roof
+            assert!(parts.iter().any(|p| p.bounds.min.y == ground));
+            assert!(parts.iter().any(|p| (p.bounds.max.y - (ground + height)).abs() < 1e-3));
+        }
+        assert_eq!(seen.len(), 7, "only saw {:?}", seen);
+    }
+
+    #[test]
+    fn round_parts_collide_round() {
+        let part = Part::new(Shape::Cylinder, Vec3::ZERO, Vec3::new(-3.0, 0.0, -3.0), Vec3::new(3.0, 20.0, 3.0), Vec3::ONE, Facade::Office);
+        assert!(part.contains_point(Vec3::new(2.5, 10.0, 0.0)));
+        // Inside the bounding box but off the round wall
+        assert!(!part.contains_point(Vec3::new(2.5, 10.0, 2.5)));
+
+        let spire = Part { shape: Shape::Cone, ..part };
+        assert!(spire.contains_point(Vec3::new(0.0, 19.0, 0.0)));
+        assert!(!spire.contains_point(Vec3::new(2.0, 19.0, 0.0)));
+    }
+}
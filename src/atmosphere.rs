@@ -0,0 +1,193 @@
+use glam::Vec3;
+
+use crate::gl_utils::ShaderProgram;
+use crate::terrain::SEA_LEVEL;
+
+// CPU mirror of ATMOSPHERE_GLSL in shaders.rs. Keep the constants in sync.
+const PLANET_RADIUS: f32 = 6360e3;
+const ATMOSPHERE_RADIUS: f32 = 6420e3;
+const RAYLEIGH_SCATTERING: Vec3 = Vec3::new(5.802e-6, 13.558e-6, 33.1e-6);
+const RAYLEIGH_HEIGHT: f32 = 8000.0;
+const MIE_SCATTERING: f32 = 3.996e-6;
+const MIE_EXTINCTION: f32 = 4.440e-6;
+const MIE_HEIGHT: f32 = 1200.0;
+const MIE_G: f32 = 0.8;
+const OZONE_ABSORPTION: Vec3 = Vec3::new(0.650e-6, 1.881e-6, 0.085e-6);
+const SUN_INTENSITY: f32 = 20.0;
+
+// Direct sunlight on a surface facing the sun, relative to transmittance
+const SUN_LIGHT: f32 = 3.0;
+
+// Sun heights (sin of elevation) bounding the sunrise/sunset band
+const TWILIGHT_HIGH: f32 = 0.15;
+const TWILIGHT_LOW: f32 = -0.15;
+
+const NIGHT_AMBIENT: Vec3 = Vec3::new(0.002, 0.002, 0.005);
+
+// Ambient light added by a lightning flash at full brightness
+const LIGHTNING_LIGHT: Vec3 = Vec3::new(0.8, 0.85, 1.0);
+
+// Under full overcast, the share of direct light the clouds block, and the
+// share of it they scatter back down as grey ambient light
+const OVERCAST_BLOCKED: f32 = 0.9;
+const OVERCAST_DIFFUSED: f32 = 0.15;
+
+fn ray_sphere(ro: Vec3, rd: Vec3, radius: f32) -> Option<(f32, f32)> {
+    let b = ro.dot(rd);
+    let c = ro.dot(ro) - radius * radius;
+    let d = b * b - c;
+    if d < 0.0 {
+        return None;
+    }
+    let d = d.sqrt();
+    Some((-b - d, -b + d))
+}
+
+fn atmosphere_pos(camera_y: f32) -> Vec3 {
+    Vec3::new(0.0, PLANET_RADIUS + (camera_y - SEA_LEVEL).max(1.0), 0.0)
+}
+
+// Relative densities at altitude h: (rayleigh, mie, ozone)
+fn density(h: f32) -> Vec3 {
+    let ozone = (1.0 - (h - 25000.0).abs() / 15000.0).max(0.0);
+    Vec3::new((-h / RAYLEIGH_HEIGHT).exp(), (-h / MIE_HEIGHT).exp(), ozone)
+}
+
+fn extinction(density: Vec3) -> Vec3 {
+    RAYLEIGH_SCATTERING * density.x + Vec3::splat(MIE_EXTINCTION * density.y) + OZONE_ABSORPTION * density.z
+}
+
+fn exp3(v: Vec3) -> Vec3 {
+    Vec3::new(v.x.exp(), v.y.exp(), v.z.exp())
+}
+
+// Fraction of sunlight reaching p, zero when the planet is in the way
+fn sun_transmittance(p: Vec3, sun_dir: Vec3) -> Vec3 {
+    if ray_sphere(p, sun_dir, PLANET_RADIUS).is_some_and(|(near, _)| near > 0.0) {
+        return Vec3::ZERO;
+    }
+    let len = ray_sphere(p, sun_dir, ATMOSPHERE_RADIUS).map_or(0.0, |(_, far)| far);
+    const STEPS: usize = 6;
+    let ds = len / STEPS as f32;
+    let mut depth = Vec3::ZERO;
+    for i in 0..STEPS {
+        let s = p + sun_dir * ((i as f32 + 0.5) * ds);
+        depth += density(s.length() - PLANET_RADIUS) * ds;
+    }
+    exp3(-extinction(depth))
+}
+
+fn sky_radiance(ro: Vec3, rd: Vec3, sun_dir: Vec3) -> Vec3 {
+    let len = match ray_sphere(ro, rd, PLANET_RADIUS) {
+        Some((near, _)) if near > 0.0 => near,
+        _ => ray_sphere(ro, rd, ATMOSPHERE_RADIUS).map_or(0.0, |(_, far)| far),
+    };
+
+    let mu = rd.dot(sun_dir);
+    let phase_r = 3.0 / (16.0 * std::f32::consts::PI) * (1.0 + mu * mu);
+    let g2 = MIE_G * MIE_G;
+    let phase_m = 3.0 / (8.0 * std::f32::consts::PI) * (1.0 - g2) / (2.0 + g2)
+        * (1.0 + mu * mu) / (1.0 + g2 - 2.0 * MIE_G * mu).powf(1.5);
+
+    const STEPS: usize = 16;
+    let ds = len / STEPS as f32;
+    let mut optical = Vec3::ZERO;
+    let mut radiance = Vec3::ZERO;
+    for i in 0..STEPS {
+        let p = ro + rd * ((i as f32 + 0.5) * ds);
+        let d = density(p.length() - PLANET_RADIUS);
+        optical += d * ds;
+        let scattering = RAYLEIGH_SCATTERING * d.x * phase_r + Vec3::splat(MIE_SCATTERING * d.y * phase_m);
+        radiance += exp3(-extinction(optical)) * sun_transmittance(p, sun_dir) * scattering * ds;
+    }
+    radiance * SUN_INTENSITY
+}
+
+// Linear HDR lighting terms derived from the sun and moon position. Computed
+// once per frame from the same scattering model the shaders use, so the scene
+// lighting matches the sky.
+#[derive(Clone, Copy, Debug)]
+pub struct SkyPalette {
+    // Sky pass
+    pub star_opacity: f32,
+    pub cloud_sun_light: Vec3,
+    pub cloud_ambient: Vec3,
+
+    // Scene pass
+    pub sun_light: Vec3,
+    pub moon_light: Vec3,
+    pub ambient_light: Vec3,
+    pub night_factor: f32,
+}
+
+impl SkyPalette {
+    // `overcast` runs from 0 (broken or no cloud) to 1 (a solid grey layer)
+    pub fn new(sun_dir: Vec3, moon_dir: Vec3, camera_y: f32, overcast: f32) -> Self {
+        let ro = atmosphere_pos(camera_y);
+        let sun_height = sun_dir.y;
+        // 0 at the bottom of the twilight band, 1 at the top
+        let t = ((sun_height - TWILIGHT_LOW) / (TWILIGHT_HIGH - TWILIGHT_LOW)).clamp(0.0, 1.0);
+
+        let sun_light = sun_transmittance(ro, sun_dir) * SUN_LIGHT;
+
+        // Sky light: the zenith plus a ring of directions 30 degrees up
+        let mut sky = sky_radiance(ro, Vec3::Y, sun_dir);
+        for i in 0..4 {
+            let azimuth = i as f32 * std::f32::consts::FRAC_PI_2;
+            let dir = Vec3::new(azimuth.cos() * 0.866, 0.5, azimuth.sin() * 0.866);
+            sky += sky_radiance(ro, dir, sun_dir);
+        }
+        let clear_ambient = sky / 5.0 + NIGHT_AMBIENT;
+
+        let clear_moon_light = if moon_dir.y > 0.0 {
+            Vec3::new(0.01, 0.015, 0.03) * (moon_dir.y * 2.0).clamp(0.0, 1.0)
+        } else {
+            Vec3::ZERO
+        };
+
+        // Cloud cover swaps direct light for a flat grey sky
+        let grey = Vec3::splat(clear_ambient.dot(Vec3::new(0.2126, 0.7152, 0.0722)));
+        let ambient_light = clear_ambient.lerp(grey, overcast) + (sun_light + clear_moon_light) * OVERCAST_DIFFUSED * overcast;
+        let direct = 1.0 - OVERCAST_BLOCKED * overcast;
+        let moon_light = clear_moon_light * direct;
+
+        Self {
+            star_opacity: (1.0 - t) * (1.0 - overcast),
+            // The underside of a thick layer is darker than scattered cumulus
+            cloud_sun_light: sun_light * (1.0 - 0.5 * overcast),
+            // Cloud tops see more of the sky dome than a building wall does
+            cloud_ambient: ambient_light * 1.5 + moon_light,
+            sun_light: sun_light * direct,
+            moon_light,
+            ambient_light,
+            night_factor: 1.0 - t,
+        }
+    }
+
+    // Lights up the scene and, more strongly, the clouds the bolt is in
+    pub fn add_lightning(&mut self, flash: f32) {
+        let light = LIGHTNING_LIGHT * flash;
+        self.ambient_light += light;
+        self.cloud_ambient += light * 3.0;
+    }
+
+    // Colour of fog and of the inside of a cloud
+    pub fn fog_color(&self) -> Vec3 {
+        self.cloud_ambient + self.cloud_sun_light * 0.3
+    }
+
+    pub unsafe fn apply_sky(&self, program: &ShaderProgram) {
+        program.set_f32("uStarOpacity", self.star_opacity);
+        program.set_vec3("uCloudSunLight", self.cloud_sun_light);
+        program.set_vec3("uCloudAmbient", self.cloud_ambient);
+        program.set_vec3("uFogColor", self.fog_color());
+    }
+
+    pub unsafe fn apply_scene(&self, program: &ShaderProgram) {
+        program.set_vec3("uSunLightColor", self.sun_light);
+        program.set_vec3("uMoonLightColor", self.moon_light);
+        program.set_vec3("uAmbientLight", self.ambient_light);
+        program.set_f32("uNightFactor", self.night_factor);
+        program.set_vec3("uFogColor", self.fog_color());
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use crate::archetypes::{Facade, Shape};
+use crate::culling::{Aabb, DrawStats, Frustum, Visibility};
+use crate::instancing::{InstanceBuffer, InstanceData};
+use crate::mesh::{Mesh, MeshBuilder};
+use crate::lod::{self, Lod, LodBox, BLOCK_DETAIL_CHUNKS, HORIZON_CHUNKS};
+use crate::terrain::{self, TERRAIN_COLOR};
+use crate::world::{Building, GRID_SPACING};
+
+// Chunk edge length in grid cells
+pub const CHUNK_SIZE: i32 = 16;
+// Resident chunks before the least recently used one is evicted.
+// Comfortably more than the (2 * HORIZON_CHUNKS + 1)^2 in view.
+pub const CHUNK_CACHE_CAPACITY: usize = 800;
+// GPU uploads per frame, so a burst of finished chunks doesn't hitch
+const MAX_UPLOADS_PER_FRAME: usize = 8;
+
+pub type ChunkCoord = (i32, i32);
+
+pub fn chunk_coord_at(pos: glam::Vec3) -> ChunkCoord {
+    let cell_x = (pos.x / GRID_SPACING).floor() as i32;
+    let cell_z = (pos.z / GRID_SPACING).floor() as i32;
+    cell_chunk(cell_x, cell_z)
+}
+
+// The chunk grid cell (x, z) belongs to
+pub fn cell_chunk(x: i32, z: i32) -> ChunkCoord {
+    (x.div_euclid(CHUNK_SIZE), z.div_euclid(CHUNK_SIZE))
+}
+
+// CPU side of a chunk. Built on the worker thread.
+pub struct ChunkData {
+    pub coord: ChunkCoord,
+    // Sorted by cell, x then z
+    pub buildings: Vec<Building>,
+    // Simplified stand-ins for the mid-range and horizon levels
+    pub blocks: Vec<LodBox>,
+    pub heightfield: Vec<LodBox>,
+    // The ground under the chunk at each level, indexed by `Lod as usize`.
+    // Moved out once uploaded.
+    pub terrain: Option<[MeshBuilder; Lod::ALL.len()]>,
+    // Union of the ground's and the buildings' boxes
+    pub bounds: Aabb,
+}
+
+impl ChunkData {
+    pub fn generate(coord: ChunkCoord) -> Self {
+        let (cx, cz) = coord;
+        let size = CHUNK_SIZE as f32 * GRID_SPACING;
+        let origin = glam::Vec2::new(cx as f32, cz as f32) * size;
+        let tiles = Lod::ALL.map(|lod| terrain::tile_mesh(origin, size, lod.terrain_quads()));
+        let mut bounds = tiles.iter().map(|(_, tile)| *tile).reduce(|a, b| a.union(&b)).unwrap();
+        let terrain = tiles.map(|(mesh, _)| mesh);
+
+        let mut buildings = Vec::new();
+        for x in (cx * CHUNK_SIZE)..((cx + 1) * CHUNK_SIZE) {
+            for z in (cz * CHUNK_SIZE)..((cz + 1) * CHUNK_SIZE) {
+                if let Some(building) = Building::at(x, z) {
+                    bounds = bounds.union(&building.bounds());
+                    buildings.push(building);
+                }
+            }
+        }
+        let blocks = lod::merge_blocks(&buildings);
+        let heightfield = lod::heightfield(&buildings);
+        Self { coord, buildings, blocks, heightfield, terrain: Some(terrain), bounds }
+    }
+
+    pub fn building_at(&self, x: i32, z: i32) -> Option<&Building> {
+        let index = self.buildings.binary_search_by_key(&(x, z), |b| (b.x, b.z)).ok()?;
+        Some(&self.buildings[index])
+    }
+}
+
+// One mesh per building part shape, indexed by `Shape as usize`
+pub type ShapeMeshes = [Rc<Mesh>; Shape::ALL.len()];
+// Instance buffers for each of them, in the same order
+type ShapeBuffers = [InstanceBuffer; Shape::ALL.len()];
+
+unsafe fn shape_buffers(meshes: &ShapeMeshes) -> ShapeBuffers {
+    meshes.each_ref().map(|mesh| InstanceBuffer::new(mesh))
+}
+
+// Instances of every part of `buildings`, split by shape
+fn part_instances<'a>(buildings: impl IntoIterator<Item = &'a Building>, out: &mut [Vec<InstanceData>; Shape::ALL.len()]) {
+    for part in buildings.into_iter().flat_map(|b| &b.parts) {
+        out[part.shape as usize].push(InstanceData::new(part.model_matrix(), part.color).with_facade(part.facade));
+    }
+}
+
+// Draws the non-empty buffers; returns how many draw calls that took
+unsafe fn draw_shapes(buffers: &ShapeBuffers) -> u32 {
+    let mut draw_calls = 0;
+    for buffer in buffers.iter().filter(|b| !b.is_empty()) {
+        buffer.draw();
+        draw_calls += 1;
+    }
+    draw_calls
+}
+
+// A resident chunk: building data plus GPU instance buffers per level
+pub struct Chunk {
+    pub data: ChunkData,
+    full: ShapeBuffers,
+    blocks: InstanceBuffer,
+    heightfield: InstanceBuffer,
+    // A single instance of each level's ground mesh
+    terrain: [InstanceBuffer; Lod::ALL.len()],
+    last_used: u64,
+}
+
+impl Chunk {
+    unsafe fn upload(mut data: ChunkData, meshes: &ShapeMeshes, frame: u64) -> Self {
+        let mut full = shape_buffers(meshes);
+        let mut instances = Default::default();
+        part_instances(&data.buildings, &mut instances);
+        for (buffer, instances) in full.iter_mut().zip(&instances) {
+            buffer.upload(instances);
+        }
+
+        // Impostors are plain boxes
+        let cube = &meshes[Shape::Box as usize];
+        let mut blocks = InstanceBuffer::new(cube);
+        blocks.upload(&data.blocks.iter().map(LodBox::instance).collect::<Vec<_>>());
+
+        let mut heightfield = InstanceBuffer::new(cube);
+        heightfield.upload(&data.heightfield.iter().map(LodBox::instance).collect::<Vec<_>>());
+
+        // Terrain meshes are already in world space
+        let ground = InstanceData::new(glam::Mat4::IDENTITY, TERRAIN_COLOR).with_facade(Facade::Terrain);
+        let terrain = data.terrain.take().expect("terrain uploaded twice").map(|mesh| {
+            let mut buffer = InstanceBuffer::new(&Rc::new(mesh.build()));
+            buffer.upload(&[ground]);
+            buffer
+        });
+
+        Self { data, full, blocks, heightfield, terrain, last_used: frame }
+    }
+}
+
+pub struct ChunkCache {
+    chunks: HashMap<ChunkCoord, Chunk>,
+    ready: Vec<ChunkData>,
+    pending: HashSet<ChunkCoord>,
+    requests: Sender<ChunkCoord>,
+    results: Receiver<ChunkData>,
+    // Survivors of per-building culling in partially visible chunks
+    partial: ShapeBuffers,
+    partial_data: [Vec<InstanceData>; Shape::ALL.len()],
+    meshes: ShapeMeshes,
+    frame: u64,
+    // Every chunk in range of `wanted_center`, nearest first
+    wanted: Vec<ChunkCoord>,
+    wanted_center: Option<ChunkCoord>,
+}
+
+impl ChunkCache {
+    pub fn new(meshes: ShapeMeshes) -> Self {
+        let (request_tx, request_rx) = mpsc::channel::<ChunkCoord>();
+        let (result_tx, result_rx) = mpsc::channel();
+
+        // Exits once the cache (and with it the request sender) is dropped
+        thread::Builder::new()
+            .name("chunk-gen".into())
+            .spawn(move || {
+                for coord in request_rx {
+                    if result_tx.send(ChunkData::generate(coord)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("failed to spawn chunk generator");
+
+        Self {
+            chunks: HashMap::new(),
+            ready: Vec::new(),
+            pending: HashSet::new(),
+            requests: request_tx,
+            results: result_rx,
+            partial: unsafe { shape_buffers(&meshes) },
+            partial_data: Default::default(),
+            meshes,
+            frame: 0,
+            wanted: Vec::new(),
+            wanted_center: None,
+        }
+    }
+
+    // Request missing chunks around `center`, upload finished ones and evict
+    // whatever hasn't been needed for the longest time.
+    pub unsafe fn update(&mut self, center: ChunkCoord) {
+        self.frame += 1;
+
+        self.ready.extend(self.results.try_iter());
+        // Nearest first
+        self.ready.sort_by_key(|data| std::cmp::Reverse(chunk_distance(data.coord, center)));
+        for _ in 0..MAX_UPLOADS_PER_FRAME {
+            let Some(data) = self.ready.pop() else { break };
+            self.pending.remove(&data.coord);
+            self.chunks.insert(data.coord, Chunk::upload(data, &self.meshes, self.frame));
+        }
+
+        // Only changes when the player crosses into another chunk
+        if self.wanted_center != Some(center) {
+            self.wanted = chunks_around(center).collect();
+            self.wanted.sort_by_key(|&coord| chunk_distance(coord, center));
+            self.wanted_center = Some(center);
+        }
+        for &coord in &self.wanted {
+            if let Some(chunk) = self.chunks.get_mut(&coord) {
+                chunk.last_used = self.frame;
+            } else if self.pending.insert(coord) {
+                let _ = self.requests.send(coord);
+            }
+        }
+
+        while self.chunks.len() > CHUNK_CACHE_CAPACITY {
+            let oldest = self.chunks.iter()
+                .min_by_key(|(_, chunk)| chunk.last_used)
+                .map(|(&coord, _)| coord)
+                .unwrap();
+            self.chunks.remove(&oldest);
+        }
+    }
+
+    // The building in cell (x, z), if its chunk is resident. The player's own
+    // chunk is the first one streamed in, so what it can fly into is there.
+    pub fn building_at(&self, x: i32, z: i32) -> Option<&Building> {
+        self.chunks.get(&cell_chunk(x, z))?.data.building_at(x, z)
+    }
+
+    // Draw resident chunks around `center` at the level of detail for their
+    // ring. Chunks outside the frustum or beyond `max_distance` are skipped.
+    // Full-detail chunks fully inside draw their static buffer and the rest
+    // are culled per building into a shared buffer.
+    pub unsafe fn draw(&mut self, center: ChunkCoord, frustum: &Frustum, camera_pos: glam::Vec3, max_distance: f32) -> DrawStats {
+        let mut stats = DrawStats::default();
+        self.partial_data.iter_mut().for_each(Vec::clear);
+
+        for coord in chunks_around(center) {
+            let Some(chunk) = self.chunks.get(&coord) else { continue };
+            let Some(lod) = Lod::for_ring(chunk_distance(coord, center)) else { continue };
+            let building_count = chunk.data.buildings.len() as u32;
+
+            let visibility = if chunk.data.bounds.distance_to(camera_pos) > max_distance {
+                Visibility::Outside
+            } else {
+                frustum.classify(&chunk.data.bounds)
+            };
+            if visibility != Visibility::Outside {
+                chunk.terrain[lod as usize].draw();
+                stats.draw_calls += 1;
+            }
+
+            match (visibility, lod) {
+                (Visibility::Outside, _) => {
+                    stats.chunks_culled += 1;
+                    stats.buildings_culled += building_count;
+                }
+                (_, Lod::Blocks | Lod::Heightfield) => {
+                    let buffer = if lod == Lod::Blocks { &chunk.blocks } else { &chunk.heightfield };
+                    buffer.draw();
+                    stats.chunks_drawn += 1;
+                    stats.impostors_drawn += buffer.len() as u32;
+                    stats.draw_calls += 1;
+                }
+                (Visibility::Inside, Lod::Full) => {
+                    stats.draw_calls += draw_shapes(&chunk.full);
+                    stats.chunks_drawn += 1;
+                    stats.buildings_drawn += building_count;
+                }
+                (Visibility::Intersecting, Lod::Full) => {
+                    stats.chunks_drawn += 1;
+                    for building in &chunk.data.buildings {
+                        let b = building.bounds();
+                        if b.distance_to(camera_pos) <= max_distance && frustum.contains(&b) {
+                            part_instances([building], &mut self.partial_data);
+                            stats.buildings_drawn += 1;
+                        } else {
+                            stats.buildings_culled += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        for (buffer, instances) in self.partial.iter_mut().zip(&self.partial_data) {
+            buffer.upload(instances);
+        }
+        stats.draw_calls += draw_shapes(&self.partial);
+        stats
+    }
+
+    // Draw shadow casters seen by a light's frustum. Full detail out to the
+    // block ring so impostor boxes don't shade the roofs under them, merged
+    // blocks beyond that for the long shadows of a low sun. The ground casts
+    // at the level it is drawn at, so hills don't shadow themselves. Returns
+    // the number of draw calls.
+    pub unsafe fn draw_shadow_casters(&self, center: ChunkCoord, frustum: &Frustum) -> u32 {
+        let mut draw_calls = 0;
+        for coord in chunks_around(center) {
+            let Some(chunk) = self.chunks.get(&coord) else { continue };
+            let Some(lod) = Lod::for_ring(chunk_distance(coord, center)) else { continue };
+            if !frustum.contains(&chunk.data.bounds) {
+                continue;
+            }
+            chunk.terrain[lod as usize].draw();
+            draw_calls += 1;
+            if chunk_distance(coord, center) <= BLOCK_DETAIL_CHUNKS {
+                draw_calls += draw_shapes(&chunk.full);
+            } else {
+                chunk.blocks.draw();
+                draw_calls += 1;
+            }
+        }
+        draw_calls
+    }
+}
+
+fn chunks_around(center: ChunkCoord) -> impl Iterator<Item = ChunkCoord> {
+    let r = HORIZON_CHUNKS;
+    (-r..=r).flat_map(move |dx| (-r..=r).map(move |dz| (center.0 + dx, center.1 + dz)))
+}
+
+fn chunk_distance(a: ChunkCoord, b: ChunkCoord) -> i32 {
+    (a.0 - b.0).abs().max((a.1 - b.1).abs())
+}
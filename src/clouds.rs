@@ -0,0 +1,200 @@
+use glam::{Vec2, Vec3};
+
+use crate::gl_utils::ShaderProgram;
+use crate::noise::mix;
+pub use crate::noise::{fbm, noise};
+
+// CPU port of CLOUDS_GLSL in shaders.rs, close enough to the shader that
+// gameplay agrees with what's on screen. Keep the two in sync; the noise
+// underneath comes from noise.rs.
+
+// Fog extinction per unit of distance inside a cloud of density 1
+pub const CLOUD_FOG_EXTINCTION: f32 = 0.08;
+// Pitch/roll rate (radians per second) of the buffeting in a dense cloud
+const TURBULENCE: f32 = 1.2;
+
+// Where the cloud layer sits and how much of it there is. The weather sets
+// this each frame; the sky and the scene's cloud shadows both read it, so
+// they stay in step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CloudLayer {
+    pub bottom: f32,
+    pub top: f32,
+    // 0 is a clear sky, 1 solid overcast
+    pub coverage: f32,
+    // How far the wind has carried the clouds
+    pub offset: Vec3,
+}
+
+impl Default for CloudLayer {
+    fn default() -> Self {
+        Self { bottom: 150.0, top: 250.0, coverage: 0.4, offset: Vec3::ZERO }
+    }
+}
+
+impl CloudLayer {
+    pub unsafe fn apply(&self, program: &ShaderProgram) {
+        program.set_f32("uCloudBottom", self.bottom);
+        program.set_f32("uCloudTop", self.top);
+        program.set_f32("uCloudCoverage", self.coverage);
+        program.set_vec3("uCloudOffset", self.offset);
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Cloud density (0..1) at p
+pub fn map_clouds(p: Vec3, layer: &CloudLayer) -> f32 {
+    if p.y < layer.bottom || p.y > layer.top {
+        return 0.0;
+    }
+    let h_norm = (p.y - layer.bottom) / (layer.top - layer.bottom);
+    let h_density = 1.0 - ((h_norm - 0.5).abs() * 2.0).powi(2);
+
+    let q = p - layer.offset;
+    let base = fbm(q * 0.015);
+    let detail = fbm(q * 0.05 + Vec3::new(2.3, 4.1, 1.2));
+    let f = base - detail * 0.3;
+    let edge = mix(0.7, -0.05, layer.coverage);
+    (smoothstep(edge, edge + 0.3, f) * h_density).clamp(0.0, 1.0)
+}
+
+// Distance at which things fade to 5% contrast in a cloud of this density.
+// Infinite in clear air.
+pub fn visibility(density: f32) -> f32 {
+    if density <= 0.0 {
+        return f32::INFINITY;
+    }
+    3.0 / (density * CLOUD_FOG_EXTINCTION)
+}
+
+// Smoothly varying pitch and roll rates that shake the plane inside a cloud
+pub fn turbulence(density: f32, time: f32) -> Vec2 {
+    let t = time * 2.5;
+    let shake = Vec2::new(noise(Vec3::new(t, 0.0, 0.0)), noise(Vec3::new(0.0, t, 7.3))) - Vec2::splat(0.5);
+    shake * 2.0 * density * TURBULENCE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gl_utils::{headless, Framebuffer, ShaderProgram, TextureFormat};
+    use crate::shaders::POST_VERTEX_SHADER;
+
+    const SIZE: i32 = 32;
+    const LAYER: CloudLayer = CloudLayer { bottom: 140.0, top: 260.0, coverage: 0.55, offset: Vec3::new(130.0, 0.0, 52.0) };
+
+    // Evaluates the GLSL functions on a grid of points, one per pixel:
+    // r = mapClouds, g = fbm, b = noise
+    const PROBE_SHADER: &str = r#"
+        #version 330 core
+        in vec2 TexCoord;
+        out vec4 FragColor;
+
+        uniform vec3 uOrigin;
+        uniform float uSpacing;
+
+        #include "clouds"
+
+        void main() {
+            vec3 p = uOrigin + vec3(floor(gl_FragCoord.x), 0.0, floor(gl_FragCoord.y)) * uSpacing;
+            p.y += floor(gl_FragCoord.x) * 3.0;
+            FragColor = vec4(mapClouds(p), fbm(p * 0.015), noise(p * 0.1), 1.0);
+        }
+    "#;
+
+    fn probe_point(origin: Vec3, spacing: f32, x: i32, z: i32) -> Vec3 {
+        let mut p = origin + Vec3::new(x as f32, 0.0, z as f32) * spacing;
+        p.y += x as f32 * 3.0;
+        p
+    }
+
+    unsafe fn run_probe(origin: Vec3, spacing: f32) -> Vec<[f32; 4]> {
+        let program = ShaderProgram::new(POST_VERTEX_SHADER, PROBE_SHADER).unwrap();
+        let float_format = TextureFormat { internal: gl::RGBA32F, format: gl::RGBA, ty: gl::FLOAT };
+        let target = Framebuffer::new(SIZE, SIZE, float_format, false).unwrap();
+        let mut vao = 0;
+        gl::GenVertexArrays(1, &mut vao);
+
+        target.bind();
+        program.bind();
+        program.set_vec3("uOrigin", origin);
+        program.set_f32("uSpacing", spacing);
+        LAYER.apply(&program);
+        gl::BindVertexArray(vao);
+        gl::DrawArrays(gl::TRIANGLES, 0, 3);
+
+        let mut pixels = vec![[0.0f32; 4]; (SIZE * SIZE) as usize];
+        gl::ReadPixels(0, 0, SIZE, SIZE, gl::RGBA, gl::FLOAT, pixels.as_mut_ptr() as *mut _);
+        gl::DeleteVertexArrays(1, &vao);
+        pixels
+    }
+
+    #[test]
+    fn matches_the_shader() {
+        let Some(_gl) = headless::context() else { return };
+        // Spans the layer and a few thousand units of drift. Spacings are
+        // exact in binary so both sides start from identical points.
+        for (origin, spacing) in [(Vec3::new(-200.0, 140.0, -300.0), 7.25), (Vec3::new(2500.0, 155.0, -4100.0), 41.0)] {
+            let pixels = unsafe { run_probe(origin, spacing) };
+            let mut errors = [Vec::new(), Vec::new(), Vec::new()];
+            for z in 0..SIZE {
+                for x in 0..SIZE {
+                    let p = probe_point(origin, spacing, x, z);
+                    let gpu = pixels[(z * SIZE + x) as usize];
+                    let cpu = [map_clouds(p, &LAYER), fbm(p * 0.015), noise(p * 0.1)];
+                    for i in 0..3 {
+                        errors[i].push((gpu[i] - cpu[i]).abs());
+                    }
+                }
+            }
+            // The hash's fract() can flip between 0 and 1 on a last-bit
+            // difference, so individual noise samples may disagree outright.
+            // Those flips are rare and mostly smoothed out in the density,
+            // but where one lands on the steep part of the coverage edge the
+            // density follows it by up to a tenth. Everywhere else it stays
+            // within 0.02.
+            for (name, e) in ["mapClouds", "fbm", "noise"].iter().zip(&mut errors) {
+                e.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let p99 = e[e.len() * 99 / 100];
+                assert!(p99 < 1e-2, "{} differs by {} at the 99th percentile", name, p99);
+            }
+            let flipped = errors[0].iter().filter(|&&e| e >= 0.02).count();
+            assert!(flipped <= 4, "mapClouds differs by 0.02 or more at {} points", flipped);
+            let worst_density = errors[0].last().unwrap();
+            assert!(*worst_density < 0.15, "mapClouds differs by up to {}", worst_density);
+        }
+    }
+
+    #[test]
+    fn clear_air_outside_the_layer() {
+        for y in [LAYER.bottom - 1.0, LAYER.top + 1.0, 0.0] {
+            assert_eq!(map_clouds(Vec3::new(12.0, y, 34.0), &LAYER), 0.0);
+        }
+        assert_eq!(visibility(0.0), f32::INFINITY);
+        assert_eq!(turbulence(0.0, 3.0), Vec2::ZERO);
+    }
+
+    fn sample_layer(layer: &CloudLayer) -> Vec<f32> {
+        (0..400).map(|i| map_clouds(Vec3::new((i % 20) as f32 * 37.0, 200.0, (i / 20) as f32 * 37.0), layer)).collect()
+    }
+
+    #[test]
+    fn layer_has_clouds_and_gaps() {
+        let samples = sample_layer(&CloudLayer::default());
+        assert!(samples.iter().any(|&d| d > 0.5));
+        assert!(samples.contains(&0.0));
+        assert!(samples.iter().all(|&d| (0.0..=1.0).contains(&d)));
+    }
+
+    #[test]
+    fn coverage_thickens_the_layer() {
+        let total = |coverage| sample_layer(&CloudLayer { coverage, ..CloudLayer::default() }).iter().sum::<f32>();
+        let (clear, fair, overcast) = (total(0.0), total(0.4), total(1.0));
+        assert!(clear < fair && fair < overcast, "{} {} {}", clear, fair, overcast);
+        assert!(overcast > 400.0 * 0.6, "overcast only averages {}", overcast / 400.0);
+    }
+}
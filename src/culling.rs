@@ -0,0 +1,100 @@
+use glam::{Mat4, Vec3, Vec4};
+
+// Axis-aligned bounding box in world space
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb { min: self.min.min(other.min), max: self.max.max(other.max) }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    // Strictly inside, as collisions always were: grazing a wall or
+    // skimming a roof is no crash
+    pub fn contains_point(&self, p: Vec3) -> bool {
+        p.cmpgt(self.min).all() && p.cmplt(self.max).all()
+    }
+
+    // Closest distance from a point to the box (0 when inside)
+    pub fn distance_to(&self, p: Vec3) -> f32 {
+        (p.clamp(self.min, self.max) - p).length()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    Outside,
+    Intersecting,
+    Inside,
+}
+
+// Six clip planes (xyz = inward normal, w = distance) of a view-projection matrix
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    // Gribb/Hartmann plane extraction, valid for OpenGL clip space (-w..w)
+    pub fn from_matrix(view_proj: Mat4) -> Self {
+        let r0 = view_proj.row(0);
+        let r1 = view_proj.row(1);
+        let r2 = view_proj.row(2);
+        let r3 = view_proj.row(3);
+
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r3 + r2, r3 - r2]
+            .map(|p| p / p.truncate().length());
+
+        Self { planes }
+    }
+
+    pub fn classify(&self, aabb: &Aabb) -> Visibility {
+        let mut result = Visibility::Inside;
+        for plane in &self.planes {
+            let normal = plane.truncate();
+            // Box corner furthest along the plane normal ("positive vertex") and its opposite
+            let positive = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.max, aabb.min);
+            let negative = Vec3::select(normal.cmpge(Vec3::ZERO), aabb.min, aabb.max);
+
+            if normal.dot(positive) + plane.w < 0.0 {
+                return Visibility::Outside;
+            }
+            if normal.dot(negative) + plane.w < 0.0 {
+                result = Visibility::Intersecting;
+            }
+        }
+        result
+    }
+
+    pub fn contains(&self, aabb: &Aabb) -> bool {
+        self.classify(aabb) != Visibility::Outside
+    }
+}
+
+// Per-frame draw counters, shown in the window title
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DrawStats {
+    pub chunks_drawn: u32,
+    pub chunks_culled: u32,
+    pub buildings_drawn: u32,
+    pub buildings_culled: u32,
+    pub impostors_drawn: u32,
+    pub draw_calls: u32,
+}
+
+impl std::ops::AddAssign for DrawStats {
+    fn add_assign(&mut self, other: DrawStats) {
+        self.chunks_drawn += other.chunks_drawn;
+        self.chunks_culled += other.chunks_culled;
+        self.buildings_drawn += other.buildings_drawn;
+        self.buildings_culled += other.buildings_culled;
+        self.impostors_drawn += other.impostors_drawn;
+        self.draw_calls += other.draw_calls;
+    }
+}
@@ -0,0 +1,256 @@
+use glam::Vec3;
+
+use crate::archetypes::{Archetype, Facade};
+use crate::noise::noise;
+use crate::terrain::{height_at, SEA_LEVEL};
+use crate::world::{BLOCK_SIZE, GRID_SPACING};
+
+// The district map varies over this many city blocks. Urban intensity peaks
+// in a few downtown cores and falls off through midrise rings into the
+// suburbs; land use mixes in parks and industry at a finer scale.
+const URBAN_SCALE: f32 = 1.0 / 11.0;
+const LAND_USE_SCALE: f32 = 1.0 / 4.0;
+// Thresholds on the fields above
+const DOWNTOWN_URBAN: f32 = 0.68;
+const MIDRISE_URBAN: f32 = 0.56;
+const PARK_LAND_USE: f32 = 0.7;
+const INDUSTRIAL_LAND_USE: f32 = 0.3;
+// Blocks whose middle is less than this far above the sea line the shore
+const WATERFRONT_RISE: f32 = 4.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum District {
+    Downtown,
+    Midrise,
+    Residential,
+    Industrial,
+    Park,
+    Waterfront,
+}
+
+// What gets built on a district's lots
+#[derive(Debug)]
+pub struct DistrictStyle {
+    // Share of lots with a building on them
+    pub density: f32,
+    // Heights to the main roof; most buildings stay near the low end
+    pub min_height: f32,
+    pub max_height: f32,
+    // Scales building widths; 1 is a 5-unit tower on an 8-unit lot
+    pub footprint: f32,
+    pub facade: Facade,
+    // sRGB
+    pub palette: &'static [[f32; 3]],
+    // Relative odds of each shape
+    pub archetypes: &'static [(Archetype, u32)],
+}
+
+impl District {
+    pub fn name(self) -> &'static str {
+        match self {
+            District::Downtown => "downtown",
+            District::Midrise => "midrise",
+            District::Residential => "residential",
+            District::Industrial => "industrial",
+            District::Park => "park",
+            District::Waterfront => "waterfront",
+        }
+    }
+
+    pub fn style(self) -> &'static DistrictStyle {
+        match self {
+            District::Downtown => &DOWNTOWN,
+            District::Midrise => &MIDRISE,
+            District::Residential => &RESIDENTIAL,
+            District::Industrial => &INDUSTRIAL,
+            District::Park => &PARK,
+            District::Waterfront => &WATERFRONT,
+        }
+    }
+}
+
+// The district of the city block containing cell (x, z)
+pub fn district_at(x: i32, z: i32) -> District {
+    let (bx, bz) = (x.div_euclid(BLOCK_SIZE) as f32, z.div_euclid(BLOCK_SIZE) as f32);
+    let middle = (Vec3::new(bx, 0.0, bz) + 0.5) * BLOCK_SIZE as f32 * GRID_SPACING;
+    if height_at(middle.x, middle.z) < SEA_LEVEL + WATERFRONT_RISE {
+        return District::Waterfront;
+    }
+    // Each field is its own plane through the noise lattice
+    let field = |scale: f32, plane: f32| noise(Vec3::new(bx * scale, plane, bz * scale));
+
+    let urban = field(URBAN_SCALE, 7.0);
+    if urban > DOWNTOWN_URBAN {
+        return District::Downtown;
+    }
+    if urban > MIDRISE_URBAN {
+        return District::Midrise;
+    }
+    match field(LAND_USE_SCALE, 19.0) {
+        u if u > PARK_LAND_USE => District::Park,
+        u if u < INDUSTRIAL_LAND_USE => District::Industrial,
+        _ => District::Residential,
+    }
+}
+
+// Glass, steel and pale stone
+static DOWNTOWN: DistrictStyle = DistrictStyle {
+    density: 0.92,
+    min_height: 60.0,
+    max_height: 200.0,
+    footprint: 1.2,
+    facade: Facade::Curtain,
+    palette: &[
+        [0.45, 0.55, 0.65],
+        [0.42, 0.58, 0.58],
+        [0.35, 0.37, 0.40],
+        [0.82, 0.80, 0.76],
+        [0.55, 0.48, 0.38],
+    ],
+    archetypes: &[
+        (Archetype::Setback, 30),
+        (Archetype::TwinTowers, 15),
+        (Archetype::PodiumTower, 25),
+        (Archetype::Cylinder, 15),
+        (Archetype::Block, 15),
+    ],
+};
+
+// Concrete and sandstone offices
+static MIDRISE: DistrictStyle = DistrictStyle {
+    density: 0.85,
+    min_height: 24.0,
+    max_height: 70.0,
+    footprint: 1.0,
+    facade: Facade::Office,
+    palette: &[
+        [0.68, 0.67, 0.64],
+        [0.76, 0.68, 0.55],
+        [0.45, 0.55, 0.65],
+        [0.82, 0.80, 0.76],
+        [0.60, 0.62, 0.63],
+    ],
+    archetypes: &[
+        (Archetype::Block, 25),
+        (Archetype::Setback, 20),
+        (Archetype::PodiumTower, 25),
+        (Archetype::Cylinder, 10),
+        (Archetype::LShape, 20),
+    ],
+};
+
+// Small brick and render houses and flats with gardens between them
+static RESIDENTIAL: DistrictStyle = DistrictStyle {
+    density: 0.7,
+    min_height: 8.0,
+    max_height: 22.0,
+    footprint: 0.8,
+    facade: Facade::Residential,
+    palette: &[
+        [0.62, 0.42, 0.35],
+        [0.85, 0.80, 0.68],
+        [0.72, 0.50, 0.38],
+        [0.76, 0.68, 0.55],
+        [0.62, 0.68, 0.72],
+    ],
+    archetypes: &[(Archetype::Block, 65), (Archetype::LShape, 35)],
+};
+
+// Wide low sheds in rust, metal and concrete
+static INDUSTRIAL: DistrictStyle = DistrictStyle {
+    density: 0.65,
+    min_height: 8.0,
+    max_height: 18.0,
+    footprint: 1.25,
+    facade: Facade::Industrial,
+    palette: &[
+        [0.55, 0.36, 0.28],
+        [0.52, 0.55, 0.57],
+        [0.68, 0.67, 0.64],
+        [0.35, 0.37, 0.40],
+        [0.45, 0.52, 0.45],
+    ],
+    archetypes: &[(Archetype::Warehouse, 70), (Archetype::Block, 20), (Archetype::LShape, 10)],
+};
+
+// Lawns and trees only (see archetypes::park_parts)
+static PARK: DistrictStyle = DistrictStyle {
+    density: 0.0,
+    min_height: 0.0,
+    max_height: 0.0,
+    footprint: 1.0,
+    facade: Facade::Plain,
+    palette: &[],
+    archetypes: &[],
+};
+
+// Brick warehouses and lofts along the shore
+static WATERFRONT: DistrictStyle = DistrictStyle {
+    density: 0.7,
+    min_height: 10.0,
+    max_height: 34.0,
+    footprint: 1.1,
+    facade: Facade::Loft,
+    palette: &[
+        [0.62, 0.42, 0.35],
+        [0.48, 0.32, 0.28],
+        [0.76, 0.68, 0.55],
+        [0.58, 0.58, 0.55],
+        [0.82, 0.80, 0.76],
+    ],
+    archetypes: &[
+        (Archetype::Warehouse, 40),
+        (Archetype::Block, 30),
+        (Archetype::LShape, 20),
+        (Archetype::PodiumTower, 10),
+    ],
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::{get_building_info, is_road, lot_ground};
+
+    const ALL: [District; 6] = [
+        District::Downtown,
+        District::Midrise,
+        District::Residential,
+        District::Industrial,
+        District::Park,
+        District::Waterfront,
+    ];
+
+    // Dry land; out at sea there is nothing to zone
+    fn cells() -> impl Iterator<Item = (i32, i32)> {
+        (-300..300).step_by(5).flat_map(|x| (-300..300).step_by(5).map(move |z| (x, z)))
+            .filter(|&(x, z)| lot_ground(x, z) > SEA_LEVEL)
+    }
+
+    #[test]
+    fn every_district_has_its_place() {
+        let mut counts = [0usize; 6];
+        for (x, z) in cells() {
+            counts[district_at(x, z) as usize] += 1;
+        }
+        let total = counts.iter().sum::<usize>() as f32;
+        for (district, count) in ALL.iter().zip(counts) {
+            let share = count as f32 / total;
+            assert!((0.03..0.6).contains(&share), "{:?} covers {:.2}", district, share);
+        }
+    }
+
+    #[test]
+    fn downtown_towers_over_the_suburbs() {
+        let mut heights: [Vec<f32>; 6] = Default::default();
+        for (x, z) in cells().filter(|&(x, z)| !is_road(x, z)) {
+            heights[district_at(x, z) as usize].push(get_building_info(x, z).map_or(0.0, |(height, _)| height));
+        }
+        let mean = |d: District| {
+            let h = &heights[d as usize];
+            h.iter().sum::<f32>() / h.len() as f32
+        };
+        assert!(mean(District::Downtown) > 2.0 * mean(District::Midrise));
+        assert!(mean(District::Midrise) > 2.0 * mean(District::Residential));
+        assert_eq!(mean(District::Park), 0.0);
+    }
+}
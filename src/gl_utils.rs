@@ -1,8 +1,211 @@
-use std::ffi::CString;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::sync::OnceLock;
 
-pub unsafe fn compile_shader(src: &str, shader_type: gl::types::GLenum) -> u32 {
+use crate::shaders::{FALLBACK_FRAGMENT_SHADER, FALLBACK_VERTEX_SHADER, SHADER_MODULES};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+    Link,
+}
+
+impl ShaderStage {
+    fn from_gl(shader_type: gl::types::GLenum) -> Self {
+        match shader_type {
+            gl::VERTEX_SHADER => ShaderStage::Vertex,
+            _ => ShaderStage::Fragment,
+        }
+    }
+}
+
+impl fmt::Display for ShaderStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderStage::Vertex => write!(f, "vertex shader compilation"),
+            ShaderStage::Fragment => write!(f, "fragment shader compilation"),
+            ShaderStage::Link => write!(f, "program linking"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ShaderError {
+    pub stage: ShaderStage,
+    // Source lines referenced by the log, as ("line" or "module:line", text)
+    pub source_lines: Vec<(String, String)>,
+    pub log: String,
+}
+
+impl ShaderError {
+    // `src` is the shader and the module sources it was compiled with
+    fn new(stage: ShaderStage, src: Option<(&str, &[&str])>, log: String) -> Self {
+        let mut source_lines: Vec<(String, String)> = Vec::new();
+        if let Some((src, modules)) = src {
+            for (source, number) in log.lines().filter_map(log_line_number) {
+                // Source string 0 is the shader itself, the rest are included modules
+                let (label, text) = match source {
+                    0 => (number.to_string(), src),
+                    i => match (SHADER_MODULES.get(i - 1), modules.get(i - 1)) {
+                        (Some((name, _)), Some(module)) => (format!("{}:{}", name, number), *module),
+                        _ => continue,
+                    },
+                };
+                if source_lines.iter().any(|(l, _)| *l == label) { continue; }
+                if let Some(line) = text.lines().nth(number.wrapping_sub(1)) {
+                    source_lines.push((label, line.trim().to_string()));
+                }
+            }
+        }
+        Self { stage, source_lines, log }
+    }
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} failed", self.stage)?;
+        for (label, text) in &self.source_lines {
+            writeln!(f, "  {:>4} | {}", label, text)?;
+        }
+        write!(f, "{}", self.log.trim_end())
+    }
+}
+
+impl std::error::Error for ShaderError {}
+
+// Pulls (source string, line) out of a driver log line. Covers the common formats:
+//   Mesa    "0:27(12): error: ..."
+//   NVIDIA  "0(27) : error C0000: ..."
+//   AMD     "ERROR: 0:27: ..."
+fn log_line_number(line: &str) -> Option<(usize, usize)> {
+    let rest = line.trim_start();
+    let rest = rest.strip_prefix("ERROR: ")
+        .or_else(|| rest.strip_prefix("WARNING: "))
+        .unwrap_or(rest);
+
+    let file_end = rest.find(|c: char| !c.is_ascii_digit())?;
+    let source = rest[..file_end].parse().ok()?;
+    let rest = &rest[file_end..];
+    let rest = rest.strip_prefix(':').or_else(|| rest.strip_prefix('('))?;
+
+    let line_end = rest.find(|c: char| !c.is_ascii_digit())?;
+    Some((source, rest[..line_end].parse().ok()?))
+}
+
+// Which GLSL dialect the current context speaks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderApi {
+    Gl,
+    Gles,
+}
+
+static SHADER_API: OnceLock<ShaderApi> = OnceLock::new();
+
+impl ShaderApi {
+    // Reads GL_VERSION ("OpenGL ES 3.2 Mesa ..." on GLES) and remembers the
+    // answer for every shader compiled afterwards. Call once the context is current.
+    pub unsafe fn detect() -> ShaderApi {
+        let version = gl::GetString(gl::VERSION);
+        let api = if !version.is_null() && CStr::from_ptr(version as *const _).to_string_lossy().starts_with("OpenGL ES") {
+            ShaderApi::Gles
+        } else {
+            ShaderApi::Gl
+        };
+        *SHADER_API.get_or_init(|| api)
+    }
+
+    pub fn current() -> ShaderApi {
+        SHADER_API.get().copied().unwrap_or(ShaderApi::Gl)
+    }
+
+    // `#version` and the defines the shaders test for
+    fn version(&self) -> &'static str {
+        match self {
+            ShaderApi::Gl => "#version 330 core\n",
+            ShaderApi::Gles => "#version 300 es\n#define GLES 1\n",
+        }
+    }
+
+    // GLES has no default float precision in fragment shaders, and none at
+    // all for the 3D, array and shadow samplers
+    fn precision(&self) -> &'static str {
+        match self {
+            ShaderApi::Gl => "",
+            ShaderApi::Gles => concat!(
+                "precision highp float;\n",
+                "precision highp int;\n",
+                "precision highp sampler3D;\n",
+                "precision highp sampler2DArray;\n",
+                "precision highp sampler2DShadow;\n",
+                "precision highp sampler2DArrayShadow;\n",
+            ),
+        }
+    }
+}
+
+// The embedded source of each of SHADER_MODULES, in order
+pub fn embedded_modules() -> Vec<&'static str> {
+    SHADER_MODULES.iter().map(|(_, src)| *src).collect()
+}
+
+// Rewrites a shader for the current API. The source's own `#version` is
+// replaced by the API's, `#include "name"` pulls in that entry of
+// SHADER_MODULES from `modules` (one source per entry, as from
+// `embedded_modules`), `#extension` directives are hoisted to just after
+// `#version`, since they must come before any code, precision statements
+// included, and `#line` directives keep driver log line numbers pointing at
+// the original sources.
+pub fn preprocess(src: &str, modules: &[&str]) -> String {
+    preprocess_for(ShaderApi::current(), src, modules)
+}
+
+fn preprocess_for(api: ShaderApi, src: &str, modules: &[&str]) -> String {
+    let mut extensions = String::new();
+    let mut body = String::from("#line 1 0\n");
+    expand(src, 0, modules, &mut body, &mut extensions, 0);
+    format!("{}{}{}{}", api.version(), extensions, api.precision(), body)
+}
+
+const MAX_INCLUDE_DEPTH: usize = 8;
+
+fn expand(src: &str, source: usize, modules: &[&str], body: &mut String, extensions: &mut String, depth: usize) {
+    for (i, line) in src.lines().enumerate() {
+        let directive = line.trim_start();
+        if directive.starts_with("#version") {
+            body.push('\n');
+        } else if directive.starts_with("#extension") {
+            extensions.push_str(directive);
+            extensions.push('\n');
+            body.push('\n');
+        } else if let Some(name) = include_name(directive) {
+            let module = SHADER_MODULES.iter().position(|(n, _)| *n == name);
+            match module.and_then(|index| Some((index, *modules.get(index)?))) {
+                Some((index, module)) if depth < MAX_INCLUDE_DEPTH => {
+                    body.push_str(&format!("#line 1 {}\n", index + 1));
+                    expand(module, index + 1, modules, body, extensions, depth + 1);
+                    body.push_str(&format!("#line {} {}\n", i + 2, source));
+                }
+                // Fails the compile with a readable message at the right line
+                _ => body.push_str(&format!("#error cannot include \"{}\"\n", name)),
+            }
+        } else {
+            body.push_str(line);
+            body.push('\n');
+        }
+    }
+}
+
+pub fn include_name(directive: &str) -> Option<&str> {
+    let rest = directive.strip_prefix("#include")?.trim();
+    rest.strip_prefix('"')?.strip_suffix('"')
+}
+
+pub unsafe fn compile_shader(src: &str, modules: &[&str], shader_type: gl::types::GLenum) -> Result<u32, ShaderError> {
     let shader = gl::CreateShader(shader_type);
-    let c_str = CString::new(src.as_bytes()).unwrap();
+    let c_str = CString::new(preprocess(src, modules)).unwrap();
     gl::ShaderSource(shader, 1, &c_str.as_ptr(), std::ptr::null());
     gl::CompileShader(shader);
 
@@ -12,33 +215,437 @@ pub unsafe fn compile_shader(src: &str, shader_type: gl::types::GLenum) -> u32 {
     if success != gl::TRUE as i32 {
         let mut len = 0;
         gl::GetShaderiv(shader, gl::INFO_LOG_LENGTH, &mut len);
-        let mut buffer = Vec::with_capacity(len as usize);
-        buffer.set_len((len as usize) - 1); // skip null terminator
-        gl::GetShaderInfoLog(shader, len, std::ptr::null_mut(), buffer.as_mut_ptr() as *mut i8);
-        panic!("Shader compilation failed: {}", String::from_utf8_lossy(&buffer));
+        let mut buffer = vec![0u8; len.max(1) as usize];
+        let mut written = 0;
+        gl::GetShaderInfoLog(shader, len, &mut written, buffer.as_mut_ptr() as *mut i8);
+        buffer.truncate(written.max(0) as usize);
+        gl::DeleteShader(shader);
+        let log = String::from_utf8_lossy(&buffer).into_owned();
+        return Err(ShaderError::new(ShaderStage::from_gl(shader_type), Some((src, modules)), log));
     }
-    shader
+    Ok(shader)
 }
 
-pub unsafe fn link_program(vs: u32, fs: u32) -> u32 {
+// Links and consumes both shaders, whether or not linking succeeds
+pub unsafe fn link_program(vs: u32, fs: u32) -> Result<u32, ShaderError> {
     let program = gl::CreateProgram();
     gl::AttachShader(program, vs);
     gl::AttachShader(program, fs);
     gl::LinkProgram(program);
-    
+    gl::DeleteShader(vs);
+    gl::DeleteShader(fs);
+
     // Check for errors
     let mut success = gl::FALSE as i32;
     gl::GetProgramiv(program, gl::LINK_STATUS, &mut success);
     if success != gl::TRUE as i32 {
-         let mut len = 0;
+        let mut len = 0;
         gl::GetProgramiv(program, gl::INFO_LOG_LENGTH, &mut len);
-        let mut buffer = Vec::with_capacity(len as usize);
-        buffer.set_len((len as usize) - 1);
-        gl::GetProgramInfoLog(program, len, std::ptr::null_mut(), buffer.as_mut_ptr() as *mut i8);
-        panic!("Program linking failed: {}", String::from_utf8_lossy(&buffer));
+        let mut buffer = vec![0u8; len.max(1) as usize];
+        let mut written = 0;
+        gl::GetProgramInfoLog(program, len, &mut written, buffer.as_mut_ptr() as *mut i8);
+        buffer.truncate(written.max(0) as usize);
+        gl::DeleteProgram(program);
+        let log = String::from_utf8_lossy(&buffer).into_owned();
+        return Err(ShaderError::new(ShaderStage::Link, None, log));
+    }
+
+    Ok(program)
+}
+
+// A linked program with its uniform locations resolved once, up front
+pub struct ShaderProgram {
+    id: u32,
+    uniforms: HashMap<String, i32>,
+    warned: RefCell<HashSet<String>>,
+}
+
+impl ShaderProgram {
+    pub unsafe fn new(vertex_src: &str, fragment_src: &str) -> Result<Self, ShaderError> {
+        Self::with_modules(vertex_src, fragment_src, &embedded_modules())
+    }
+
+    // Like `new`, with the given sources for SHADER_MODULES rather than the
+    // embedded ones
+    pub unsafe fn with_modules(vertex_src: &str, fragment_src: &str, modules: &[&str]) -> Result<Self, ShaderError> {
+        let vs = compile_shader(vertex_src, modules, gl::VERTEX_SHADER)?;
+        let fs = match compile_shader(fragment_src, modules, gl::FRAGMENT_SHADER) {
+            Ok(fs) => fs,
+            Err(e) => {
+                gl::DeleteShader(vs);
+                return Err(e);
+            }
+        };
+        Ok(Self::from_id(link_program(vs, fs)?))
+    }
+
+    // Like `new`, but reports the error and substitutes the magenta debug
+    // shader instead of failing. Keeps the original vertex stage if it was
+    // fine so geometry still lands in the right place.
+    pub unsafe fn new_or_fallback(vertex_src: &str, fragment_src: &str, modules: &[&str]) -> Self {
+        match Self::with_modules(vertex_src, fragment_src, modules) {
+            Ok(program) => program,
+            Err(e) => {
+                eprintln!("Shader error, using fallback: {}", e);
+                Self::with_modules(vertex_src, FALLBACK_FRAGMENT_SHADER, modules)
+                    .or_else(|_| Self::new(FALLBACK_VERTEX_SHADER, FALLBACK_FRAGMENT_SHADER))
+                    .expect("fallback shader failed to build")
+            }
+        }
+    }
+
+    unsafe fn from_id(id: u32) -> Self {
+        let mut count = 0;
+        gl::GetProgramiv(id, gl::ACTIVE_UNIFORMS, &mut count);
+        let mut max_len = 0;
+        gl::GetProgramiv(id, gl::ACTIVE_UNIFORM_MAX_LENGTH, &mut max_len);
+
+        let mut uniforms = HashMap::new();
+        for index in 0..count as u32 {
+            let mut name = vec![0u8; max_len.max(1) as usize];
+            let (mut len, mut size, mut ty) = (0, 0, 0);
+            gl::GetActiveUniform(id, index, max_len, &mut len, &mut size, &mut ty, name.as_mut_ptr() as *mut i8);
+            name.truncate(len as usize);
+
+            let c_name = CString::new(name).unwrap();
+            let location = gl::GetUniformLocation(id, c_name.as_ptr());
+            let mut name = c_name.into_string().unwrap();
+            // Arrays are reported as "name[0]"; look them up by their bare name
+            if let Some(stripped) = name.strip_suffix("[0]") {
+                name = stripped.to_string();
+            }
+            uniforms.insert(name, location);
+        }
+
+        Self { id, uniforms, warned: RefCell::new(HashSet::new()) }
+    }
+
+    pub unsafe fn bind(&self) {
+        gl::UseProgram(self.id);
+    }
+
+    // Uniforms the driver optimised away (or typos) are reported once, then ignored
+    fn location(&self, name: &str) -> Option<i32> {
+        let location = self.uniforms.get(name).copied();
+        if location.is_none() && self.warned.borrow_mut().insert(name.to_string()) {
+            eprintln!("Warning: uniform '{}' not found in program {}", name, self.id);
+        }
+        location
+    }
+
+    pub unsafe fn set_mat4(&self, name: &str, value: &glam::Mat4) {
+        if let Some(loc) = self.location(name) {
+            gl::UniformMatrix4fv(loc, 1, gl::FALSE, value.as_ref().as_ptr());
+        }
+    }
+
+    pub unsafe fn set_mat4_array(&self, name: &str, values: &[glam::Mat4]) {
+        if let Some(loc) = self.location(name) {
+            gl::UniformMatrix4fv(loc, values.len() as i32, gl::FALSE, values.as_ptr() as *const f32);
+        }
+    }
+
+    pub unsafe fn set_vec3(&self, name: &str, value: glam::Vec3) {
+        if let Some(loc) = self.location(name) {
+            gl::Uniform3f(loc, value.x, value.y, value.z);
+        }
+    }
+
+    pub unsafe fn set_f32(&self, name: &str, value: f32) {
+        if let Some(loc) = self.location(name) {
+            gl::Uniform1f(loc, value);
+        }
+    }
+
+    pub unsafe fn set_f32_array(&self, name: &str, values: &[f32]) {
+        if let Some(loc) = self.location(name) {
+            gl::Uniform1fv(loc, values.len() as i32, values.as_ptr());
+        }
+    }
+
+    pub unsafe fn set_vec2(&self, name: &str, value: glam::Vec2) {
+        if let Some(loc) = self.location(name) {
+            gl::Uniform2f(loc, value.x, value.y);
+        }
+    }
+
+    pub unsafe fn set_ivec2(&self, name: &str, x: i32, y: i32) {
+        if let Some(loc) = self.location(name) {
+            gl::Uniform2i(loc, x, y);
+        }
+    }
+
+    // Also used for sampler units
+    pub unsafe fn set_i32(&self, name: &str, value: i32) {
+        if let Some(loc) = self.location(name) {
+            gl::Uniform1i(loc, value);
+        }
+    }
+}
+
+impl Drop for ShaderProgram {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteProgram(self.id) };
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureFormat {
+    pub internal: u32,
+    pub format: u32,
+    pub ty: u32,
+}
+
+impl TextureFormat {
+    pub const RGBA16F: TextureFormat = TextureFormat { internal: gl::RGBA16F, format: gl::RGBA, ty: gl::HALF_FLOAT };
+    pub const RGBA8: TextureFormat = TextureFormat { internal: gl::RGBA8, format: gl::RGBA, ty: gl::UNSIGNED_BYTE };
+    pub const DEPTH24: TextureFormat = TextureFormat { internal: gl::DEPTH_COMPONENT24, format: gl::DEPTH_COMPONENT, ty: gl::UNSIGNED_INT };
+}
+
+// Owned 2D texture, clamped and linearly filtered
+pub struct Texture {
+    id: u32,
+    pub width: i32,
+    pub height: i32,
+    pub format: TextureFormat,
+}
+
+impl Texture {
+    pub unsafe fn new(width: i32, height: i32, format: TextureFormat) -> Self {
+        let mut id = 0;
+        gl::GenTextures(1, &mut id);
+        gl::BindTexture(gl::TEXTURE_2D, id);
+        gl::TexImage2D(gl::TEXTURE_2D, 0, format.internal as i32, width, height, 0, format.format, format.ty, std::ptr::null());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        Self { id, width, height, format }
+    }
+
+    // Uploads float RGBA pixels, rows bottom to top
+    pub unsafe fn from_rgba(width: i32, height: i32, format: TextureFormat, pixels: &[[f32; 4]]) -> Self {
+        let texture = Self::new(width, height, format);
+        texture.upload_rgba(pixels);
+        texture
+    }
+
+    // Replaces the whole image; leaves the texture bound to the active unit
+    pub unsafe fn upload_rgba(&self, pixels: &[[f32; 4]]) {
+        assert_eq!(pixels.len(), (self.width * self.height) as usize);
+        gl::BindTexture(gl::TEXTURE_2D, self.id);
+        gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, self.width, self.height, gl::RGBA, gl::FLOAT, pixels.as_ptr() as *const _);
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub unsafe fn bind(&self, unit: u32) {
+        gl::ActiveTexture(gl::TEXTURE0 + unit);
+        gl::BindTexture(gl::TEXTURE_2D, self.id);
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteTextures(1, &self.id) };
+    }
+}
+
+// Owned framebuffer object with either a sampleable colour or depth texture,
+// or multisampled renderbuffers (resolve those with `blit_to`)
+pub struct Framebuffer {
+    id: u32,
+    pub width: i32,
+    pub height: i32,
+    color: Option<Texture>,
+    depth: Option<Texture>,
+    renderbuffers: Vec<u32>,
+}
+
+impl Framebuffer {
+    // Colour texture, plus a depth renderbuffer if `depth` is set
+    pub unsafe fn new(width: i32, height: i32, format: TextureFormat, depth: bool) -> Result<Self, String> {
+        let color = Texture::new(width, height, format);
+        let mut fb = Self::empty(width, height);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, color.id, 0);
+        fb.color = Some(color);
+        if depth {
+            fb.attach_renderbuffer(gl::DEPTH_COMPONENT24, gl::DEPTH_ATTACHMENT, 0);
+        }
+        fb.check()
+    }
+
+    // Depth texture only, set up for shadow comparisons (sampler2DShadow)
+    pub unsafe fn depth_only(width: i32, height: i32) -> Result<Self, String> {
+        let depth = Texture::new(width, height, TextureFormat::DEPTH24);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as i32);
+        let mut fb = Self::empty(width, height);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, depth.id, 0);
+        gl::DrawBuffers(1, &gl::NONE);
+        gl::ReadBuffer(gl::NONE);
+        fb.depth = Some(depth);
+        fb.check()
+    }
+
+    // Multisampled colour + depth, for rendering only
+    pub unsafe fn multisampled(width: i32, height: i32, format: TextureFormat, samples: i32) -> Result<Self, String> {
+        let mut fb = Self::empty(width, height);
+        fb.attach_renderbuffer(format.internal, gl::COLOR_ATTACHMENT0, samples);
+        fb.attach_renderbuffer(gl::DEPTH_COMPONENT24, gl::DEPTH_ATTACHMENT, samples);
+        fb.check()
+    }
+
+    unsafe fn empty(width: i32, height: i32) -> Self {
+        let mut id = 0;
+        gl::GenFramebuffers(1, &mut id);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, id);
+        Self { id, width, height, color: None, depth: None, renderbuffers: Vec::new() }
+    }
+
+    unsafe fn attach_renderbuffer(&mut self, internal: u32, attachment: u32, samples: i32) {
+        let mut rb = 0;
+        gl::GenRenderbuffers(1, &mut rb);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, rb);
+        if samples > 0 {
+            gl::RenderbufferStorageMultisample(gl::RENDERBUFFER, samples, internal, self.width, self.height);
+        } else {
+            gl::RenderbufferStorage(gl::RENDERBUFFER, internal, self.width, self.height);
+        }
+        gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, attachment, gl::RENDERBUFFER, rb);
+        self.renderbuffers.push(rb);
+    }
+
+    unsafe fn check(self) -> Result<Self, String> {
+        let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(format!("framebuffer incomplete (status 0x{:x})", status));
+        }
+        Ok(self)
+    }
+
+    pub fn color(&self) -> &Texture {
+        self.color.as_ref().expect("framebuffer has no colour texture")
+    }
+
+    pub fn depth(&self) -> &Texture {
+        self.depth.as_ref().expect("framebuffer has no depth texture")
+    }
+
+    // Binds for drawing and sets the viewport to cover it
+    pub unsafe fn bind(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.id);
+        gl::Viewport(0, 0, self.width, self.height);
+    }
+
+    pub unsafe fn bind_default(width: i32, height: i32) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Viewport(0, 0, width, height);
+    }
+
+    // Copies (and resolves, if multisampled) colour into `target`
+    pub unsafe fn blit_to(&self, target: &Framebuffer) {
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.id);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, target.id);
+        gl::BlitFramebuffer(0, 0, self.width, self.height, 0, 0, target.width, target.height, gl::COLOR_BUFFER_BIT, gl::NEAREST);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteRenderbuffers(self.renderbuffers.len() as i32, self.renderbuffers.as_ptr());
+            gl::DeleteFramebuffers(1, &self.id);
+        }
+    }
+}
+
+// Surfaceless EGL context for tests that render offscreen. Tests holding one
+// are serialised, since contexts share the process-wide GL function pointers
+// and EGL display. None when the machine has no usable GPU or software driver,
+// in which case the calling test says it was skipped on the real stderr,
+// past the test harness's output capture, so a pass can't hide it.
+#[cfg(test)]
+pub mod headless {
+    use std::io::Write;
+    use std::sync::{Mutex, MutexGuard};
+
+    use glutin::api::egl::context::PossiblyCurrentContext;
+    use glutin::api::egl::device::Device;
+    use glutin::api::egl::display::Display;
+    use glutin::config::{ConfigSurfaceTypes, ConfigTemplateBuilder};
+    use glutin::context::{ContextApi, ContextAttributesBuilder, Version};
+    use glutin::prelude::*;
+
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    pub struct HeadlessContext {
+        _context: PossiblyCurrentContext,
+        _display: Display,
+        _guard: MutexGuard<'static, ()>,
+    }
+
+    pub fn context() -> Option<HeadlessContext> {
+        let context = create();
+        if context.is_none() {
+            let test = std::thread::current().name().unwrap_or("test").to_string();
+            let _ = writeln!(std::io::stderr(), "SKIPPED {}: no headless GL context", test);
+        }
+        context
+    }
+
+    fn create() -> Option<HeadlessContext> {
+        let guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let device = Device::query_devices().ok()?.next()?;
+        let display = unsafe { Display::with_device(&device, None) }.ok()?;
+        let template = ConfigTemplateBuilder::new().with_surface_type(ConfigSurfaceTypes::empty()).build();
+        let config = unsafe { display.find_configs(template) }.ok()?.next()?;
+        let attributes = ContextAttributesBuilder::new()
+            .with_context_api(ContextApi::OpenGl(Some(Version::new(3, 3))))
+            .build(None);
+        let context = unsafe { display.create_context(&config, &attributes) }.ok()?
+            .make_current_surfaceless().ok()?;
+        gl::load_with(|symbol| {
+            let symbol = std::ffi::CString::new(symbol).unwrap();
+            display.get_proc_address(symbol.as_c_str()).cast()
+        });
+        unsafe { super::ShaderApi::detect() };
+        Some(HeadlessContext { _context: context, _display: display, _guard: guard })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extensions_precede_precision() {
+        let src = "#version 330 core\n#extension GL_EXT_shadow_samplers : enable\nvoid main() {}\n";
+        let out = preprocess_for(ShaderApi::Gles, src, &embedded_modules());
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(&lines[..3], ["#version 300 es", "#define GLES 1", "#extension GL_EXT_shadow_samplers : enable"]);
+        assert!(lines[3].starts_with("precision "));
+        assert_eq!(out.matches("#version").count(), 1);
+        assert_eq!(out.matches("#extension").count(), 1);
+        // Both directives leave a blank line behind so line numbers hold
+        let body = out.split_once("#line 1 0\n").unwrap().1;
+        assert_eq!(body.lines().nth(2), Some("void main() {}"));
+    }
+
+    #[test]
+    fn errors_quote_the_module_source_compiled() {
+        let Some(_gl) = headless::context() else { return };
+        let color = SHADER_MODULES.iter().position(|(n, _)| *n == "color").unwrap();
+        let mut modules = embedded_modules();
+        modules[color] = "\nvec3 broken() { return undeclared; }\n";
+        let src = "#version 330 core\n#include \"color\"\nout vec4 FragColor;\nvoid main() { FragColor = vec4(broken(), 1.0); }\n";
+        let Err(e) = (unsafe { ShaderProgram::with_modules(FALLBACK_VERTEX_SHADER, src, &modules) }) else {
+            panic!("broken module compiled");
+        };
+        assert!(e.source_lines.contains(&("color:2".to_string(), "vec3 broken() { return undeclared; }".to_string())), "{}", e);
     }
-    
-    gl::DeleteShader(vs);
-    gl::DeleteShader(fs);
-    program
 }
@@ -0,0 +1,294 @@
+use glam::Vec2;
+
+use crate::gl_utils::{Framebuffer, Texture, TextureFormat};
+use crate::hot_reload::ReloadableProgram;
+use crate::shaders::{
+    BLOOM_DOWNSAMPLE_FRAGMENT_SHADER, BLOOM_UPSAMPLE_FRAGMENT_SHADER, POST_VERTEX_SHADER, TONEMAP_FRAGMENT_SHADER,
+};
+
+const MSAA_SAMPLES: i32 = 4;
+
+// Bloom mip chain, starting at half resolution
+const BLOOM_LEVELS: usize = 5;
+const BLOOM_THRESHOLD: f32 = 1.0;
+const BLOOM_KNEE: f32 = 0.5;
+const BLOOM_RADIUS: f32 = 1.0;
+const BLOOM_STRENGTH: f32 = 0.05;
+
+// Auto-exposure maps the scene's average luminance to this mid grey. The
+// limits keep night dark and noon from washing out, rather than letting the
+// meter normalise everything to the same brightness.
+const EXPOSURE_KEY: f32 = 0.18;
+const MIN_EXPOSURE: f32 = 0.1;
+const MAX_EXPOSURE: f32 = 8.0;
+// Per second, in stops
+const ADAPTATION_SPEED: f32 = 1.5;
+// Largest side of the mip level the meter reads back
+const METERING_SIZE: i32 = 16;
+
+// Reads back a tiny mip of the scene each frame to find its average
+// luminance. Reads go through two pixel buffers, so the CPU only ever maps
+// the one the GPU filled a frame ago and never waits on the current frame.
+struct ExposureMeter {
+    pbos: [u32; 2],
+    // Pixel count and format of what was read into each buffer
+    pending: [Option<(usize, TextureFormat)>; 2],
+    frame: usize,
+    read_fbo: u32,
+}
+
+impl ExposureMeter {
+    unsafe fn new() -> Self {
+        let mut pbos = [0; 2];
+        gl::GenBuffers(2, pbos.as_mut_ptr());
+        let mut read_fbo = 0;
+        gl::GenFramebuffers(1, &mut read_fbo);
+        Self { pbos, pending: [None; 2], frame: 0, read_fbo }
+    }
+
+    // Log2 of the average scene luminance, one frame behind
+    unsafe fn measure(&mut self, scene: &Texture) -> Option<f32> {
+        let mut level = 0;
+        while (scene.width >> level) > METERING_SIZE || (scene.height >> level) > METERING_SIZE {
+            level += 1;
+        }
+        let width = (scene.width >> level).max(1);
+        let height = (scene.height >> level).max(1);
+        let pixels = (width * height) as usize;
+        let (ty, pixel_bytes) = if scene.format == TextureFormat::RGBA8 {
+            (gl::UNSIGNED_BYTE, 4)
+        } else {
+            (gl::FLOAT, 16)
+        };
+
+        scene.bind(0);
+        gl::GenerateMipmap(gl::TEXTURE_2D);
+
+        let write = self.frame % 2;
+        let read = 1 - write;
+        self.frame += 1;
+
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.read_fbo);
+        gl::FramebufferTexture2D(gl::READ_FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, scene.id(), level);
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, self.pbos[write]);
+        gl::BufferData(gl::PIXEL_PACK_BUFFER, (pixels * pixel_bytes) as isize, std::ptr::null(), gl::STREAM_READ);
+        gl::ReadPixels(0, 0, width, height, gl::RGBA, ty, std::ptr::null_mut());
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        self.pending[write] = Some((pixels, scene.format));
+
+        let result = self.pending[read].take().and_then(|(pixels, format)| {
+            gl::BindBuffer(gl::PIXEL_PACK_BUFFER, self.pbos[read]);
+            let bytes = pixels * if format == TextureFormat::RGBA8 { 4 } else { 16 };
+            let ptr = gl::MapBufferRange(gl::PIXEL_PACK_BUFFER, 0, bytes as isize, gl::MAP_READ_BIT);
+            if ptr.is_null() {
+                return None;
+            }
+            let rgb = |i: usize| -> [f32; 3] {
+                if format == TextureFormat::RGBA8 {
+                    let p = std::slice::from_raw_parts(ptr as *const u8, pixels * 4);
+                    [p[i * 4] as f32 / 255.0, p[i * 4 + 1] as f32 / 255.0, p[i * 4 + 2] as f32 / 255.0]
+                } else {
+                    let p = std::slice::from_raw_parts(ptr as *const f32, pixels * 4);
+                    [p[i * 4], p[i * 4 + 1], p[i * 4 + 2]]
+                }
+            };
+            let log_sum: f32 = (0..pixels).map(|i| {
+                let [r, g, b] = rgb(i);
+                (0.2126 * r + 0.7152 * g + 0.0722 * b).max(1e-5).log2()
+            }).sum();
+            gl::UnmapBuffer(gl::PIXEL_PACK_BUFFER);
+            Some(log_sum / pixels as f32)
+        });
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        result
+    }
+}
+
+impl Drop for ExposureMeter {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(2, self.pbos.as_ptr());
+            gl::DeleteFramebuffers(1, &self.read_fbo);
+        }
+    }
+}
+
+struct Targets {
+    // Multisampled render target, resolved into `scene` each frame
+    msaa: Option<Framebuffer>,
+    scene: Framebuffer,
+    bloom: Vec<Framebuffer>,
+}
+
+impl Targets {
+    unsafe fn new(width: i32, height: i32, format: TextureFormat, samples: i32) -> Self {
+        let msaa = if samples > 1 {
+            Framebuffer::multisampled(width, height, format, samples)
+                .map_err(|e| eprintln!("No multisampled HDR target ({}), rendering without MSAA", e))
+                .ok()
+        } else {
+            None
+        };
+        let scene = Framebuffer::new(width, height, format, msaa.is_none()).expect("HDR scene target");
+
+        let mut bloom = Vec::new();
+        let (mut w, mut h) = (width, height);
+        for _ in 0..BLOOM_LEVELS {
+            w = (w / 2).max(1);
+            h = (h / 2).max(1);
+            bloom.push(Framebuffer::new(w, h, format, false).expect("bloom target"));
+        }
+        Self { msaa, scene, bloom }
+    }
+}
+
+// Renders the frame into a floating point target, then resolves it to the
+// display through bloom, auto-exposure and a filmic tonemapper. Everything
+// before `finish` works in linear HDR; only the tonemap pass writes sRGB.
+pub struct HdrPipeline {
+    width: i32,
+    height: i32,
+    format: TextureFormat,
+    samples: i32,
+    targets: Targets,
+    meter: ExposureMeter,
+    pub exposure: f32,
+    vao: u32,
+    downsample: ReloadableProgram,
+    upsample: ReloadableProgram,
+    tonemap: ReloadableProgram,
+}
+
+impl HdrPipeline {
+    pub unsafe fn new(width: i32, height: i32) -> Self {
+        // GLES 3.0 without EXT_color_buffer_float can't render to half floats
+        let format = match Framebuffer::new(1, 1, TextureFormat::RGBA16F, false) {
+            Ok(_) => TextureFormat::RGBA16F,
+            Err(e) => {
+                eprintln!("No float render targets ({}), falling back to 8-bit HDR", e);
+                TextureFormat::RGBA8
+            }
+        };
+        let mut max_samples = 0;
+        gl::GetIntegerv(gl::MAX_SAMPLES, &mut max_samples);
+        let samples = MSAA_SAMPLES.min(max_samples);
+
+        let width = width.max(1);
+        let height = height.max(1);
+        let mut vao = 0;
+        gl::GenVertexArrays(1, &mut vao);
+
+        Self {
+            width,
+            height,
+            format,
+            samples,
+            targets: Targets::new(width, height, format, samples),
+            meter: ExposureMeter::new(),
+            exposure: 1.0,
+            vao,
+            downsample: ReloadableProgram::new("bloom_down", POST_VERTEX_SHADER, BLOOM_DOWNSAMPLE_FRAGMENT_SHADER),
+            upsample: ReloadableProgram::new("bloom_up", POST_VERTEX_SHADER, BLOOM_UPSAMPLE_FRAGMENT_SHADER),
+            tonemap: ReloadableProgram::new("tonemap", POST_VERTEX_SHADER, TONEMAP_FRAGMENT_SHADER),
+        }
+    }
+
+    pub unsafe fn resize(&mut self, width: i32, height: i32) {
+        let width = width.max(1);
+        let height = height.max(1);
+        if (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            self.targets = Targets::new(width, height, self.format, self.samples);
+        }
+    }
+
+    // Binds the HDR target for the scene passes
+    pub unsafe fn begin(&mut self) {
+        self.downsample.poll();
+        self.upsample.poll();
+        self.tonemap.poll();
+        match &self.targets.msaa {
+            Some(msaa) => msaa.bind(),
+            None => self.targets.scene.bind(),
+        }
+    }
+
+    // Resolves the frame into `output`, or the window if None
+    pub unsafe fn finish(&mut self, dt: f32, output: Option<&Framebuffer>) {
+        let targets = &self.targets;
+        if let Some(msaa) = &targets.msaa {
+            msaa.blit_to(&targets.scene);
+        }
+
+        if let Some(log_average) = self.meter.measure(targets.scene.color()) {
+            let target = (EXPOSURE_KEY / log_average.exp2()).clamp(MIN_EXPOSURE, MAX_EXPOSURE);
+            // Adapt in log space so brightening and darkening feel alike
+            let blend = 1.0 - (-dt * ADAPTATION_SPEED).exp();
+            self.exposure = (self.exposure.log2() + (target.log2() - self.exposure.log2()) * blend).exp2();
+        }
+
+        gl::Disable(gl::DEPTH_TEST);
+        gl::DepthMask(gl::FALSE);
+        gl::BindVertexArray(self.vao);
+
+        // Bloom: threshold and downsample into the chain...
+        let program = self.downsample.program();
+        program.bind();
+        program.set_i32("uSource", 0);
+        program.set_f32("uThreshold", BLOOM_THRESHOLD);
+        program.set_f32("uKnee", BLOOM_KNEE);
+        let mut source = targets.scene.color();
+        for (i, level) in targets.bloom.iter().enumerate() {
+            level.bind();
+            source.bind(0);
+            program.set_vec2("uTexelSize", texel_size(source));
+            program.set_i32("uFirstPass", (i == 0) as i32);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            source = level.color();
+        }
+
+        // ...then blur back up, adding each level onto the next larger one
+        let program = self.upsample.program();
+        program.bind();
+        program.set_i32("uSource", 0);
+        program.set_f32("uRadius", BLOOM_RADIUS);
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::ONE, gl::ONE);
+        for pair in targets.bloom.windows(2).rev() {
+            pair[0].bind();
+            pair[1].color().bind(0);
+            program.set_vec2("uTexelSize", texel_size(pair[1].color()));
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        }
+        gl::Disable(gl::BLEND);
+
+        match output {
+            Some(fb) => fb.bind(),
+            None => Framebuffer::bind_default(self.width, self.height),
+        }
+        let program = self.tonemap.program();
+        program.bind();
+        targets.scene.color().bind(0);
+        targets.bloom[0].color().bind(1);
+        program.set_i32("uScene", 0);
+        program.set_i32("uBloom", 1);
+        program.set_f32("uExposure", self.exposure);
+        program.set_f32("uBloomStrength", BLOOM_STRENGTH);
+        gl::DrawArrays(gl::TRIANGLES, 0, 3);
+
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::Enable(gl::DEPTH_TEST);
+        gl::DepthMask(gl::TRUE);
+    }
+}
+
+impl Drop for HdrPipeline {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteVertexArrays(1, &self.vao) };
+    }
+}
+
+fn texel_size(texture: &Texture) -> Vec2 {
+    Vec2::new(1.0 / texture.width as f32, 1.0 / texture.height as f32)
+}
@@ -0,0 +1,199 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Once;
+use std::time::SystemTime;
+
+use crate::gl_utils::{include_name, ShaderProgram};
+use crate::shaders::SHADER_MODULES;
+
+// Debug builds read GLSL from a `shaders/` directory next to the binary and
+// rebuild programs when the files change. Files are seeded from the embedded
+// sources, so there is always something to edit. A `.orig` copy of what was
+// seeded lets a later build with changed embedded sources replace stale files
+// without clobbering edits otherwise. Release builds only ever use the
+// embedded constants. The shared `#include` modules are seeded the same way,
+// as `<name>.glsl`, and an edit to one rebuilds every program including it.
+// Tests stick to the embedded sources and leave the disk alone.
+pub const HOT_RELOAD: bool = cfg!(all(debug_assertions, not(test)));
+
+struct ShaderFile {
+    path: Option<PathBuf>,
+    embedded: &'static str,
+    modified: Option<SystemTime>,
+}
+
+impl ShaderFile {
+    // Exports the embedded source first
+    fn new(file_name: &str, embedded: &'static str) -> Self {
+        if let Some(path) = shader_path(file_name) {
+            export(&path, embedded.trim_start_matches('\n'));
+        }
+        Self::open(file_name, embedded)
+    }
+
+    fn open(file_name: &str, embedded: &'static str) -> Self {
+        let mut file = Self { path: shader_path(file_name), embedded, modified: None };
+        file.modified = file.mtime();
+        file
+    }
+
+    fn mtime(&self) -> Option<SystemTime> {
+        let path = self.path.as_ref()?;
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    fn source(&self) -> String {
+        self.path.as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .unwrap_or_else(|| self.embedded.to_string())
+    }
+
+    // True once per change on disk
+    fn changed(&mut self) -> bool {
+        let modified = self.mtime();
+        if modified.is_some() && modified != self.modified {
+            self.modified = modified;
+            return true;
+        }
+        false
+    }
+}
+
+// Every program shares the module files, so they are exported only once
+fn module_file(index: usize) -> ShaderFile {
+    static EXPORTED: Once = Once::new();
+    EXPORTED.call_once(|| {
+        for (name, embedded) in SHADER_MODULES {
+            ShaderFile::new(&format!("{}.glsl", name), embedded);
+        }
+    });
+    let (name, embedded) = SHADER_MODULES[index];
+    ShaderFile::open(&format!("{}.glsl", name), embedded)
+}
+
+// Indices into SHADER_MODULES of everything `src` includes, directly or
+// through other modules
+fn included_modules(src: &str, found: &mut Vec<usize>) {
+    for name in src.lines().filter_map(|line| include_name(line.trim_start())) {
+        let Some(index) = SHADER_MODULES.iter().position(|(n, _)| *n == name) else { continue };
+        if !found.contains(&index) {
+            found.push(index);
+            included_modules(&module_file(index).source(), found);
+        }
+    }
+}
+
+fn export(path: &Path, embedded: &str) {
+    let orig = path.with_extension(format!("{}.orig", path.extension().unwrap().to_string_lossy()));
+    let seeded = fs::read_to_string(&orig).ok();
+    if path.exists() && seeded.as_deref() == Some(embedded) {
+        return;
+    }
+
+    let result = fs::create_dir_all(path.parent().unwrap())
+        .and_then(|_| fs::write(path, embedded))
+        .and_then(|_| fs::write(&orig, embedded));
+    if let Err(e) = result {
+        eprintln!("Could not export shader to {}: {}", path.display(), e);
+    }
+}
+
+fn shader_dir() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    Some(exe.parent()?.join("shaders"))
+}
+
+fn shader_path(file_name: &str) -> Option<PathBuf> {
+    if HOT_RELOAD { shader_dir().map(|dir| dir.join(file_name)) } else { None }
+}
+
+// Edits can add or drop includes, so this is redone on every rebuild
+fn find_modules(vertex: &ShaderFile, fragment: &ShaderFile) -> Vec<(usize, ShaderFile)> {
+    let mut found = Vec::new();
+    included_modules(&vertex.source(), &mut found);
+    included_modules(&fragment.source(), &mut found);
+    found.into_iter().map(|index| (index, module_file(index))).collect()
+}
+
+// A source for each of SHADER_MODULES: the files for those included, read
+// once per build, and the embedded text for the rest
+fn module_sources(modules: &[(usize, ShaderFile)]) -> Vec<String> {
+    let mut sources: Vec<String> = SHADER_MODULES.iter().map(|(_, src)| src.to_string()).collect();
+    for (index, file) in modules {
+        sources[*index] = file.source();
+    }
+    sources
+}
+
+// A shader program that can be swapped out under the renderer while it runs
+pub struct ReloadableProgram {
+    name: String,
+    vertex: ShaderFile,
+    fragment: ShaderFile,
+    // The modules both stages include, with their indices into SHADER_MODULES
+    modules: Vec<(usize, ShaderFile)>,
+    program: ShaderProgram,
+}
+
+impl ReloadableProgram {
+    pub unsafe fn new(name: &str, vertex_src: &'static str, fragment_src: &'static str) -> Self {
+        let vertex = ShaderFile::new(&format!("{}.vert", name), vertex_src);
+        let fragment = ShaderFile::new(&format!("{}.frag", name), fragment_src);
+        let modules = find_modules(&vertex, &fragment);
+        let sources = module_sources(&modules);
+        let sources: Vec<&str> = sources.iter().map(String::as_str).collect();
+        let program = ShaderProgram::new_or_fallback(&vertex.source(), &fragment.source(), &sources);
+        Self { name: name.to_string(), vertex, fragment, modules, program }
+    }
+
+    pub fn program(&self) -> &ShaderProgram {
+        &self.program
+    }
+
+    // Rebuild if either file or an included module changed. A broken edit
+    // keeps the last good program.
+    pub unsafe fn poll(&mut self) {
+        if !HOT_RELOAD {
+            return;
+        }
+        // Check them all so no change is left pending
+        let vertex_changed = self.vertex.changed();
+        let fragment_changed = self.fragment.changed();
+        let mut modules_changed = false;
+        for (_, module) in &mut self.modules {
+            modules_changed |= module.changed();
+        }
+        if !vertex_changed && !fragment_changed && !modules_changed {
+            return;
+        }
+        self.modules = find_modules(&self.vertex, &self.fragment);
+
+        let sources = module_sources(&self.modules);
+        let sources: Vec<&str> = sources.iter().map(String::as_str).collect();
+        match ShaderProgram::with_modules(&self.vertex.source(), &self.fragment.source(), &sources) {
+            Ok(program) => {
+                self.program = program;
+                println!("Reloaded {} shaders", self.name);
+            }
+            Err(e) => eprintln!("Reloading {} shaders failed, keeping previous program: {}", self.name, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shaders::{SCENE_FRAGMENT_SHADER, WATER_FRAGMENT_SHADER};
+
+    #[test]
+    fn finds_every_included_module() {
+        let mut found = Vec::new();
+        included_modules(SCENE_FRAGMENT_SHADER, &mut found);
+        found.sort();
+        assert_eq!(found, (0..SHADER_MODULES.len()).collect::<Vec<_>>());
+
+        let mut found = Vec::new();
+        included_modules(WATER_FRAGMENT_SHADER, &mut found);
+        assert!(!found.contains(&SHADER_MODULES.iter().position(|(n, _)| *n == "shadows").unwrap()));
+    }
+}
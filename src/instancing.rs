@@ -0,0 +1,117 @@
+use std::rc::Rc;
+
+use crate::archetypes::Facade;
+use crate::mesh::Mesh;
+
+// Per-instance vertex data for drawing many copies of the same mesh in one call.
+// Layout (matches SCENE_VERTEX_SHADER; mesh attributes are in mesh.rs):
+//   location 1..=4 : model matrix columns
+//   location 5     : base colour
+//   location 9     : facade style (see archetypes::Facade)
+
+pub const INSTANCE_MODEL_LOCATION: u32 = 1;
+pub const INSTANCE_COLOR_LOCATION: u32 = 5;
+pub const INSTANCE_FACADE_LOCATION: u32 = 9;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct InstanceData {
+    pub model: [f32; 16],
+    pub color: [f32; 3],
+    pub facade: f32,
+}
+
+impl InstanceData {
+    // Office windows, for stand-ins that mix several buildings
+    pub fn new(model: glam::Mat4, color: glam::Vec3) -> Self {
+        Self {
+            model: model.to_cols_array(),
+            color: color.to_array(),
+            facade: Facade::Office as u32 as f32,
+        }
+    }
+
+    pub fn with_facade(self, facade: Facade) -> Self {
+        Self { facade: facade as u32 as f32, ..self }
+    }
+}
+
+// A VAO that pairs a shared mesh with its own instance VBO. Upload once when
+// the contents change, draw every frame.
+pub struct InstanceBuffer {
+    mesh: Rc<Mesh>,
+    vao: u32,
+    vbo: u32,
+    count: i32,
+    capacity: usize,
+}
+
+impl InstanceBuffer {
+    pub unsafe fn new(mesh: &Rc<Mesh>) -> Self {
+        let (mut vao, mut vbo) = (0, 0);
+        gl::GenVertexArrays(1, &mut vao);
+        gl::GenBuffers(1, &mut vbo);
+        gl::BindVertexArray(vao);
+
+        mesh.bind_attributes();
+
+        // Instance attributes
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+        let stride = std::mem::size_of::<InstanceData>() as i32;
+        for col in 0..4 {
+            let loc = INSTANCE_MODEL_LOCATION + col;
+            let offset = (col as usize * 4 * 4) as *const _;
+            gl::VertexAttribPointer(loc, 4, gl::FLOAT, gl::FALSE, stride, offset);
+            gl::EnableVertexAttribArray(loc);
+            gl::VertexAttribDivisor(loc, 1);
+        }
+        let color_offset = std::mem::offset_of!(InstanceData, color) as *const _;
+        gl::VertexAttribPointer(INSTANCE_COLOR_LOCATION, 3, gl::FLOAT, gl::FALSE, stride, color_offset);
+        gl::EnableVertexAttribArray(INSTANCE_COLOR_LOCATION);
+        gl::VertexAttribDivisor(INSTANCE_COLOR_LOCATION, 1);
+        let facade_offset = std::mem::offset_of!(InstanceData, facade) as *const _;
+        gl::VertexAttribPointer(INSTANCE_FACADE_LOCATION, 1, gl::FLOAT, gl::FALSE, stride, facade_offset);
+        gl::EnableVertexAttribArray(INSTANCE_FACADE_LOCATION);
+        gl::VertexAttribDivisor(INSTANCE_FACADE_LOCATION, 1);
+
+        gl::BindVertexArray(0);
+
+        Self { mesh: mesh.clone(), vao, vbo, count: 0, capacity: 0 }
+    }
+
+    pub unsafe fn upload(&mut self, instances: &[InstanceData]) {
+        let size = std::mem::size_of_val(instances);
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+        if instances.len() > self.capacity {
+            // Grow the store; reuse it for smaller uploads afterwards
+            gl::BufferData(gl::ARRAY_BUFFER, size as isize, instances.as_ptr() as *const _, gl::DYNAMIC_DRAW);
+            self.capacity = instances.len();
+        } else if size > 0 {
+            gl::BufferSubData(gl::ARRAY_BUFFER, 0, size as isize, instances.as_ptr() as *const _);
+        }
+        self.count = instances.len() as i32;
+    }
+
+    pub fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub unsafe fn draw(&self) {
+        if self.is_empty() { return; }
+        gl::BindVertexArray(self.vao);
+        gl::DrawElementsInstanced(gl::TRIANGLES, self.mesh.index_count(), gl::UNSIGNED_INT, std::ptr::null(), self.count);
+    }
+}
+
+impl Drop for InstanceBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.vbo);
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
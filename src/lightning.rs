@@ -0,0 +1,256 @@
+use glam::{Mat4, Vec3};
+
+use crate::clouds::CloudLayer;
+use crate::hot_reload::ReloadableProgram;
+use crate::shaders::{LIGHTNING_FRAGMENT_SHADER, LIGHTNING_VERTEX_SHADER};
+use crate::weather::Weather;
+use crate::terrain::{height_at, SEA_LEVEL};
+use crate::world::{hash, Building, GRID_SPACING};
+
+// Average seconds between strikes in a full storm
+const STRIKE_INTERVAL: f32 = 6.0;
+// Strikes land within this distance of the player, where they can be seen
+const STRIKE_RANGE: f32 = 500.0;
+// Cells searched around a strike for the tallest roof to hit
+const STRIKE_SEARCH_CELLS: i32 = 4;
+// A plane within this horizontal distance of a strike, above the roof it
+// was headed for, takes the bolt instead
+const PLAYER_HIT_RADIUS: f32 = 100.0;
+// Bolts onto towers that reach into the cloud layer still come down from
+// at least this far above them
+const MIN_BOLT_DROP: f32 = 40.0;
+
+// How long a bolt stays on screen
+const BOLT_LIFETIME: f32 = 0.4;
+const MAIN_SEGMENTS: usize = 24;
+const BRANCH_SEGMENTS: usize = 8;
+const BRANCH_CHANCE: f32 = 0.25;
+const BRANCH_BRIGHTNESS: f32 = 0.35;
+// Sideways wander per segment, relative to the bolt's length
+const JAGGEDNESS: f32 = 0.06;
+const BOLT_COLOR: Vec3 = Vec3::new(40.0, 45.0, 60.0);
+
+// Deterministic sequence of random numbers in [0, 1)
+struct Rng(i32);
+
+impl Rng {
+    fn next(&mut self) -> f32 {
+        self.0 += 1;
+        (hash(self.0, 0x1157) % 10000) as f32 / 10000.0
+    }
+}
+
+// Top centre of the highest part of anything within `radius` cells of
+// `center`; a tower on a hill beats a taller one in the valley
+pub fn tallest_building(center: Vec3, radius: i32) -> Option<Vec3> {
+    let cx = (center.x / GRID_SPACING).round() as i32;
+    let cz = (center.z / GRID_SPACING).round() as i32;
+    let mut best: Option<Vec3> = None;
+    for z in cz - radius..=cz + radius {
+        for x in cx - radius..=cx + radius {
+            let Some(building) = Building::at(x, z) else { continue };
+            let top = building.parts.iter().map(|part| part.bounds).reduce(|a, b| if b.max.y > a.max.y { b } else { a }).unwrap();
+            if best.is_none_or(|b| top.max.y > b.y) {
+                best = Some(Vec3::new(top.center().x, top.max.y, top.center().z));
+            }
+        }
+    }
+    best
+}
+
+// A line from `start` to `end` that wanders sideways, pinned at both ends
+fn jagged(start: Vec3, end: Vec3, segments: usize, rng: &mut Rng) -> Vec<Vec3> {
+    let length = start.distance(end);
+    let mut offset = Vec3::ZERO;
+    (0..=segments).map(|i| {
+        if i == segments {
+            return end;
+        }
+        let t = i as f32 / segments as f32;
+        if i > 0 {
+            offset += (Vec3::new(rng.next(), rng.next(), rng.next()) - Vec3::splat(0.5)) * length * JAGGEDNESS;
+        }
+        start.lerp(end, t) + offset * (1.0 - t)
+    }).collect()
+}
+
+// The main channel from cloud to target, plus dimmer forks off it. Each
+// strip comes with its brightness.
+fn bolt_strips(start: Vec3, end: Vec3, rng: &mut Rng) -> Vec<(Vec<Vec3>, f32)> {
+    let main = jagged(start, end, MAIN_SEGMENTS, rng);
+    let down = (end - start).normalize();
+    let mut strips = Vec::new();
+    for &from in &main[1..main.len() - 1] {
+        if rng.next() < BRANCH_CHANCE {
+            let angle = rng.next() * std::f32::consts::TAU;
+            let dir = (down + Vec3::new(angle.cos(), 0.0, angle.sin()) * 0.8).normalize();
+            let length = start.distance(end) * (0.1 + 0.2 * rng.next());
+            strips.push((jagged(from, from + dir * length, BRANCH_SEGMENTS, rng), BRANCH_BRIGHTNESS));
+        }
+    }
+    strips.insert(0, (main, 1.0));
+    strips
+}
+
+// Brightness `age` seconds into a strike: the first stroke, then a return
+// stroke down the same channel
+fn flicker(age: f32) -> f32 {
+    let pulse = |t: f32| if t >= 0.0 { (-t * 15.0).exp() } else { 0.0 };
+    pulse(age) + 0.7 * pulse(age - 0.12)
+}
+
+// Lightning during storms: strikes from the cloud layer onto the tallest
+// roof nearby, or onto the plane if it's in the way
+pub struct Lightning {
+    program: ReloadableProgram,
+    vao: u32,
+    vbo: u32,
+    // Vertices of the current bolt, uploaded on the next draw
+    vertices: Vec<[f32; 4]>,
+    dirty: bool,
+    // First vertex and vertex count of each strip
+    strips: Vec<(i32, i32)>,
+    age: f32,
+    timer: f32,
+    rng: Rng,
+}
+
+impl Lightning {
+    pub unsafe fn new() -> Self {
+        let (mut vao, mut vbo) = (0, 0);
+        gl::GenVertexArrays(1, &mut vao);
+        gl::GenBuffers(1, &mut vbo);
+        gl::BindVertexArray(vao);
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+        gl::VertexAttribPointer(0, 4, gl::FLOAT, gl::FALSE, 16, std::ptr::null());
+        gl::EnableVertexAttribArray(0);
+        gl::BindVertexArray(0);
+
+        let mut rng = Rng(0);
+        let timer = STRIKE_INTERVAL * rng.next();
+        Self {
+            program: ReloadableProgram::new("lightning", LIGHTNING_VERTEX_SHADER, LIGHTNING_FRAGMENT_SHADER),
+            vao,
+            vbo,
+            vertices: Vec::new(),
+            dirty: false,
+            strips: Vec::new(),
+            age: BOLT_LIFETIME,
+            timer,
+            rng,
+        }
+    }
+
+    // Advances the storm. Returns true when a bolt hits the plane.
+    pub fn update(&mut self, dt: f32, weather: &Weather, player: Vec3) -> bool {
+        self.age += dt;
+        // Thunderstorms need heavy rain; snow storms don't have lightning
+        let storm = ((weather.current.precipitation - 0.5) / 0.5).clamp(0.0, 1.0) * (1.0 - weather.current.snow);
+        if storm <= 0.0 {
+            return false;
+        }
+        self.timer -= dt * storm;
+        if self.timer > 0.0 {
+            return false;
+        }
+        self.timer = STRIKE_INTERVAL * (0.3 + 1.4 * self.rng.next());
+        self.strike(&weather.clouds(), player)
+    }
+
+    fn strike(&mut self, clouds: &CloudLayer, player: Vec3) -> bool {
+        let angle = self.rng.next() * std::f32::consts::TAU;
+        let distance = STRIKE_RANGE * self.rng.next().sqrt();
+        let area = player + Vec3::new(angle.cos(), 0.0, angle.sin()) * distance;
+        let ground = height_at(area.x, area.z).max(SEA_LEVEL);
+        let roof = tallest_building(area, STRIKE_SEARCH_CELLS).unwrap_or(Vec3::new(area.x, ground, area.z));
+
+        let near = Vec3::new(player.x - roof.x, 0.0, player.z - roof.z).length() < PLAYER_HIT_RADIUS;
+        let hit = near && player.y > roof.y && player.y < clouds.top;
+        let end = if hit { player } else { roof };
+        // From a little way into the cloud base, not quite straight above
+        let start = Vec3::new(
+            end.x + (self.rng.next() - 0.5) * 80.0,
+            (clouds.bottom + (clouds.top - clouds.bottom) * 0.3).max(end.y + MIN_BOLT_DROP),
+            end.z + (self.rng.next() - 0.5) * 80.0,
+        );
+
+        self.vertices.clear();
+        self.strips.clear();
+        for (points, brightness) in bolt_strips(start, end, &mut self.rng) {
+            self.strips.push((self.vertices.len() as i32, points.len() as i32));
+            self.vertices.extend(points.iter().map(|p| [p.x, p.y, p.z, brightness]));
+        }
+        self.dirty = true;
+        self.age = 0.0;
+        hit
+    }
+
+    // How brightly the current strike lights the sky, 0 when there's none
+    pub fn flash(&self) -> f32 {
+        if self.age < BOLT_LIFETIME { flicker(self.age) } else { 0.0 }
+    }
+
+    pub unsafe fn draw(&mut self, view_projection: &Mat4) {
+        if self.age >= BOLT_LIFETIME {
+            return;
+        }
+        self.program.poll();
+        gl::BindVertexArray(self.vao);
+        if self.dirty {
+            gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+            gl::BufferData(gl::ARRAY_BUFFER, (self.vertices.len() * 16) as isize, self.vertices.as_ptr() as *const _, gl::DYNAMIC_DRAW);
+            self.dirty = false;
+        }
+        let program = self.program.program();
+        program.bind();
+        program.set_mat4("uViewProjection", view_projection);
+        program.set_vec3("uColor", BOLT_COLOR);
+        program.set_f32("uIntensity", flicker(self.age));
+        for &(first, count) in &self.strips {
+            gl::DrawArrays(gl::LINE_STRIP, first, count);
+        }
+        gl::BindVertexArray(0);
+    }
+}
+
+impl Drop for Lightning {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteBuffers(1, &self.vbo);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::culling::Aabb;
+
+    #[test]
+    fn strikes_the_tallest_roof() {
+        let center = Vec3::new(300.0, 0.0, -120.0);
+        let roof = tallest_building(center, 3).unwrap();
+        let tops: Vec<Aabb> = (-3..=3).flat_map(|z| (-3..=3).map(move |x| (25 + x, -10 + z)))
+            .filter_map(|(x, z)| Building::at(x, z))
+            .flat_map(|b| b.parts.into_iter().map(|part| part.bounds))
+            .collect();
+        assert!(tops.iter().all(|top| top.max.y <= roof.y));
+        // On top of something solid, not in the air between twin towers
+        let struck = tops.iter().find(|top| top.max.y == roof.y).unwrap();
+        assert!(struck.contains_point(roof - Vec3::Y * 0.1));
+    }
+
+    #[test]
+    fn bolt_connects_cloud_and_target() {
+        let (start, end) = (Vec3::new(10.0, 180.0, 5.0), Vec3::new(0.0, 40.0, 0.0));
+        let strips = bolt_strips(start, end, &mut Rng(7));
+        let main = &strips[0].0;
+        assert_eq!((main[0], *main.last().unwrap()), (start, end));
+        // Every fork starts on the main channel
+        for (branch, brightness) in &strips[1..] {
+            assert!(main.contains(&branch[0]));
+            assert!(*brightness < 1.0);
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+
+use crate::culling::Aabb;
+use crate::instancing::InstanceData;
+use crate::world::{Building, BLOCK_SIZE};
+
+// Chunk rings (Chebyshev distance from the player's chunk) for each level.
+// The horizon ring is 12 chunks of 192 units: 2300 units along the axes and
+// more towards the corners, which fills the 2600-unit draw distance (main.rs)
+// but for a strip the haze has all but hidden by then. One ring fewer would
+// open gaps at 2100 units; one more costs another hundred resident chunks.
+pub const FULL_DETAIL_CHUNKS: i32 = 2;
+pub const BLOCK_DETAIL_CHUNKS: i32 = 5;
+pub const HORIZON_CHUNKS: i32 = 12;
+
+// Cells per side of one heightfield impostor column: two by two city blocks,
+// so each column covers whole blocks and the horizon level is never finer
+// than the one before it
+const HEIGHTFIELD_CELLS: i32 = 2 * BLOCK_SIZE;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lod {
+    // Every building as its own box
+    Full,
+    // One merged box per city block
+    Blocks,
+    // Coarse height grid out to the horizon
+    Heightfield,
+}
+
+impl Lod {
+    pub const ALL: [Lod; 3] = [Lod::Full, Lod::Blocks, Lod::Heightfield];
+
+    pub fn for_ring(ring: i32) -> Option<Lod> {
+        match ring {
+            r if r <= FULL_DETAIL_CHUNKS => Some(Lod::Full),
+            r if r <= BLOCK_DETAIL_CHUNKS => Some(Lod::Blocks),
+            r if r <= HORIZON_CHUNKS => Some(Lod::Heightfield),
+            _ => None,
+        }
+    }
+
+    // Terrain grid cells along a chunk's edge
+    pub fn terrain_quads(self) -> u32 {
+        match self {
+            Lod::Full => 32,
+            Lod::Blocks => 16,
+            Lod::Heightfield => 6,
+        }
+    }
+}
+
+// Simplified stand-in for a group of buildings
+#[derive(Clone, Copy, Debug)]
+pub struct LodBox {
+    pub bounds: Aabb,
+    pub color: glam::Vec3,
+}
+
+impl LodBox {
+    pub fn instance(&self) -> InstanceData {
+        let model = glam::Mat4::from_translation(self.bounds.center())
+            * glam::Mat4::from_scale(self.bounds.max - self.bounds.min);
+        InstanceData::new(model, self.color)
+    }
+}
+
+pub fn merge_blocks(buildings: &[Building]) -> Vec<LodBox> {
+    merge_by(buildings, |b| (b.x.div_euclid(BLOCK_SIZE), b.z.div_euclid(BLOCK_SIZE)))
+}
+
+pub fn heightfield(buildings: &[Building]) -> Vec<LodBox> {
+    merge_by(buildings, |b| (b.x.div_euclid(HEIGHTFIELD_CELLS), b.z.div_euclid(HEIGHTFIELD_CELLS)))
+}
+
+// Collapse each group into one box spanning the group's footprint, from its
+// lowest ground up to the mean roof. The box takes the mean colour too, so the
+// silhouette keeps its overall shape. Bridges are left out: as boxes they
+// would dam the water they cross.
+fn merge_by(buildings: &[Building], key: impl Fn(&Building) -> (i32, i32)) -> Vec<LodBox> {
+    let mut groups: BTreeMap<(i32, i32), Vec<&Building>> = BTreeMap::new();
+    for building in buildings.iter().filter(|b| !b.bridge) {
+        groups.entry(key(building)).or_default().push(building);
+    }
+
+    groups.values().map(|group| {
+        let n = group.len() as f32;
+        let footprint = group.iter()
+            .map(|b| b.bounds())
+            .reduce(|a, b| a.union(&b))
+            .unwrap();
+        let roof = group.iter().map(|b| b.bounds().min.y + b.height).sum::<f32>() / n;
+        let color = group.iter().map(|b| b.color).sum::<glam::Vec3>() / n;
+
+        let min = footprint.min;
+        let max = glam::Vec3::new(footprint.max.x, roof, footprint.max.z);
+        LodBox { bounds: Aabb { min, max }, color }
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::ChunkData;
+
+    #[test]
+    fn heightfield_is_coarser_than_blocks() {
+        let chunks: Vec<ChunkData> = (0..6).map(|cx| (cx, 0))
+            .map(ChunkData::generate)
+            .filter(|chunk| !chunk.blocks.is_empty())
+            .collect();
+        assert!(chunks.len() >= 3, "only {} chunks with buildings", chunks.len());
+        for chunk in &chunks {
+            let (columns, blocks) = (heightfield(&chunk.buildings).len(), merge_blocks(&chunk.buildings).len());
+            assert!(columns <= blocks, "{} columns for {} blocks", columns, blocks);
+        }
+    }
+}
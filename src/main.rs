@@ -3,6 +3,28 @@ mod gl_utils;
 mod world;
 mod player;
 mod universe;
+mod instancing;
+mod chunks;
+mod culling;
+mod lod;
+mod hot_reload;
+mod atmosphere;
+mod hdr;
+mod settings;
+mod post;
+mod shadows;
+mod clouds;
+mod weather;
+mod precipitation;
+mod lightning;
+mod mesh;
+mod archetypes;
+mod wind;
+mod thermals;
+mod districts;
+mod noise;
+mod terrain;
+mod water;
 
 use glutin::{
     config::{ConfigTemplateBuilder, GlConfig},
@@ -12,8 +34,9 @@ use glutin::{
 };
 use glutin_winit::DisplayBuilder;
 use raw_window_handle::HasRawWindowHandle;
-use std::ffi::{CStr, CString};
+use std::ffi::CString;
 use std::num::NonZeroU32;
+use std::rc::Rc;
 use winit::{
     event::{Event, WindowEvent, KeyEvent},
     event_loop::{ControlFlow, EventLoop},
@@ -22,10 +45,41 @@ use winit::{
 };
 
 use crate::player::Player;
-use crate::world::{GRID_SPACING, BUILDING_WIDTH, GROUND_LEVEL, MAX_BUILDING_HEIGHT, get_building_info, check_collision};
-use crate::gl_utils::{compile_shader, link_program};
+use crate::districts::district_at;
+use crate::world::{GRID_SPACING, MAX_BUILDING_HEIGHT, check_collision, Contact};
+use crate::terrain::{height_at, SEA_LEVEL};
+use crate::hot_reload::ReloadableProgram;
+use crate::gl_utils::ShaderApi;
+use crate::atmosphere::SkyPalette;
+use crate::hdr::HdrPipeline;
+use crate::post::{PostChain, PostFrame};
+use crate::settings::Settings;
+use crate::shadows::{ShadowCamera, ShadowMaps};
+use crate::clouds::{map_clouds, turbulence, visibility, CLOUD_FOG_EXTINCTION};
+use crate::weather::Weather;
+use crate::precipitation::Precipitation;
+use crate::lightning::Lightning;
+use crate::water::{Water, WaterFrame};
+use crate::mesh::{unit_cone, unit_cube, unit_cylinder};
+use crate::wind::wind_at;
+use crate::thermals::{solar_heating, thermal_lift};
 use crate::shaders::{SKY_VERTEX_SHADER, SKY_FRAGMENT_SHADER, SCENE_VERTEX_SHADER, SCENE_FRAGMENT_SHADER};
 use crate::universe::Universe;
+use crate::chunks::{ChunkCache, chunk_coord_at};
+use crate::culling::{DrawStats, Frustum};
+
+// Hull lost to each lightning strike, and seconds without controls after one
+const LIGHTNING_DAMAGE: f32 = 0.34;
+const LIGHTNING_STUN: f32 = 1.5;
+
+// Glider flight: it glides GLIDE_RATIO units forward per unit of height lost,
+// trimmed at GLIDER_TRIM_SPEED, and drops its nose below STALL_SPEED
+const GRAVITY: f32 = 9.81;
+const GLIDE_RATIO: f32 = 20.0;
+const GLIDER_TRIM_SPEED: f32 = 25.0;
+const GLIDER_DRAG: f32 = GRAVITY / (GLIDE_RATIO * GLIDER_TRIM_SPEED * GLIDER_TRIM_SPEED);
+const STALL_SPEED: f32 = 12.0;
+const STALL_PITCH_RATE: f32 = 0.8;
 
 fn main() {
     let event_loop = EventLoop::new().unwrap();
@@ -36,8 +90,9 @@ fn main() {
 
     let (window, gl_config) = display_builder
         .build(&event_loop, template, |configs| {
+            // Antialiasing happens in the HDR target, so the window needs no samples
             configs.reduce(|accum, config| {
-                if config.num_samples() > accum.num_samples() { config } else { accum }
+                if config.num_samples() < accum.num_samples() { config } else { accum }
             }).unwrap()
         }).unwrap();
 
@@ -46,7 +101,7 @@ fn main() {
 
     let context_attributes = ContextAttributesBuilder::new().build(raw_window_handle);
     let fallback_context_attributes = ContextAttributesBuilder::new()
-        .with_context_api(glutin::context::ContextApi::Gles(None))
+        .with_context_api(glutin::context::ContextApi::Gles(Some(glutin::context::Version::new(3, 0))))
         .build(raw_window_handle);
 
     let mut not_current_gl_context = Some(unsafe {
@@ -67,53 +122,45 @@ fn main() {
         let symbol = CString::new(symbol).unwrap();
         gl_display.get_proc_address(symbol.as_c_str()).cast()
     });
+    let shader_api = unsafe { ShaderApi::detect() };
+    println!("Shader API: {:?}", shader_api);
 
     // --- OpenGL Setup ---
-    let sky_program = unsafe {
-        let vs = compile_shader(SKY_VERTEX_SHADER, gl::VERTEX_SHADER);
-        let fs = compile_shader(SKY_FRAGMENT_SHADER, gl::FRAGMENT_SHADER);
-        link_program(vs, fs)
-    };
-
-    let scene_program = unsafe {
-        let vs = compile_shader(SCENE_VERTEX_SHADER, gl::VERTEX_SHADER);
-        let fs = compile_shader(SCENE_FRAGMENT_SHADER, gl::FRAGMENT_SHADER);
-        link_program(vs, fs)
-    };
-
-    let vertices: [f32; 108] = [
-        -0.5, -0.5, -0.5,  0.5, -0.5, -0.5,  0.5,  0.5, -0.5, 
-         0.5,  0.5, -0.5, -0.5,  0.5, -0.5, -0.5, -0.5, -0.5, 
-        -0.5, -0.5,  0.5,  0.5, -0.5,  0.5,  0.5,  0.5,  0.5, 
-         0.5,  0.5,  0.5, -0.5,  0.5,  0.5, -0.5, -0.5,  0.5, 
-        -0.5,  0.5,  0.5, -0.5,  0.5, -0.5, -0.5, -0.5, -0.5, 
-        -0.5, -0.5, -0.5, -0.5, -0.5,  0.5, -0.5,  0.5,  0.5, 
-         0.5,  0.5,  0.5,  0.5,  0.5, -0.5,  0.5, -0.5, -0.5, 
-         0.5, -0.5, -0.5,  0.5, -0.5,  0.5,  0.5,  0.5,  0.5, 
-        -0.5, -0.5, -0.5,  0.5, -0.5, -0.5,  0.5, -0.5,  0.5, 
-         0.5, -0.5,  0.5, -0.5, -0.5,  0.5, -0.5, -0.5, -0.5, 
-        -0.5,  0.5, -0.5,  0.5,  0.5, -0.5,  0.5,  0.5,  0.5, 
-         0.5,  0.5,  0.5, -0.5,  0.5,  0.5, -0.5,  0.5, -0.5, 
-    ];
-
-    let (mut vao, mut vbo) = (0, 0);
-    unsafe {
-        gl::GenVertexArrays(1, &mut vao);
-        gl::GenBuffers(1, &mut vbo);
-        gl::BindVertexArray(vao);
-        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-        gl::BufferData(gl::ARRAY_BUFFER, (vertices.len() * 4) as isize, vertices.as_ptr() as *const _, gl::STATIC_DRAW);
-        gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 3 * 4, std::ptr::null());
-        gl::EnableVertexAttribArray(0);
-        gl::Enable(gl::DEPTH_TEST);
+    let mut sky_shader = unsafe { ReloadableProgram::new("sky", SKY_VERTEX_SHADER, SKY_FRAGMENT_SHADER) };
+    let mut scene_shader = unsafe { ReloadableProgram::new("scene", SCENE_VERTEX_SHADER, SCENE_FRAGMENT_SHADER) };
+    let mut hdr = unsafe { HdrPipeline::new(attrs.width as i32, attrs.height as i32) };
+    let settings = Settings::load();
+    if let Some(seed) = settings.seed {
+        terrain::set_seed(seed);
     }
-
+    let mut post = unsafe { PostChain::new(attrs.width as i32, attrs.height as i32, settings.post.clone()) };
+    let mut shadows = unsafe { ShadowMaps::new() };
+    let mut weather = Weather::new(settings.weather);
+    let mut precipitation = unsafe { Precipitation::new() };
+    let mut lightning = unsafe { Lightning::new() };
+    let mut water = unsafe { Water::new() };
+
+    // Shared by the buildings, their impostors and the sky box
+    let cube = Rc::new(unsafe { unit_cube().build() });
+    let cylinder = Rc::new(unsafe { unit_cylinder().build() });
+    let cone = Rc::new(unsafe { unit_cone().build() });
+    unsafe { gl::Enable(gl::DEPTH_TEST) };
+
+    // Buildings and the ground under them are streamed in chunks, generated
+    // off the render thread
+    let mut chunk_cache = ChunkCache::new([cube.clone(), cylinder, cone]);
+
+    // Start above the ground, wherever the seed put it
+    let start_pos = glam::Vec3::new(0.0, height_at(0.0, 0.0).max(SEA_LEVEL) + 40.0, 0.0);
     let mut player = Player {
-        pos: glam::Vec3::new(0.0, 30.0, 0.0),
+        pos: start_pos,
         yaw: 0.0_f32.to_radians(), // Facing +X (Sunrise)
         pitch: 0.0,
         roll: 0.0,
         speed: 25.0,
+        health: 1.0,
+        stunned: 0.0,
+        glider: settings.glider,
     };
     
     let mut universe = Universe::new();
@@ -126,19 +173,26 @@ fn main() {
     let mut p_key_was_pressed = false; 
     let mut total_time_elapsed = 0.0; 
 
+    // Horizon impostors reach ~2400 units; haze swallows anything beyond this
+    let draw_distance = 2600.0;
+    let mut stats_accum = DrawStats::default();
+    let mut stats_frames = 0;
+    let mut stats_timer = 0.0;
+
     let _ = event_loop.run(move |event, target| {
         target.set_control_flow(ControlFlow::Poll);
 
         match event {
             Event::WindowEvent { event, .. } => match event {
                 WindowEvent::CloseRequested => target.exit(),
-                WindowEvent::Resized(size) => {
-                    if size.width != 0 && size.height != 0 {
-                        surface.resize(&gl_context, NonZeroU32::new(size.width).unwrap(), NonZeroU32::new(size.height).unwrap());
-                        unsafe { gl::Viewport(0, 0, size.width as i32, size.height as i32) };
+                WindowEvent::Resized(size) if size.width != 0 && size.height != 0 => {
+                    surface.resize(&gl_context, NonZeroU32::new(size.width).unwrap(), NonZeroU32::new(size.height).unwrap());
+                    unsafe {
+                        hdr.resize(size.width as i32, size.height as i32);
+                        post.resize(size.width as i32, size.height as i32);
                     }
                 }
-                WindowEvent::KeyboardInput { event: KeyEvent { physical_key: PhysicalKey::Code(keycode), state, .. }, .. } => {
+                WindowEvent::KeyboardInput { event: KeyEvent { physical_key: PhysicalKey::Code(keycode), state, repeat, .. }, .. } => {
                      match state {
                         winit::event::ElementState::Pressed => { 
                             keys_pressed.insert(keycode); 
@@ -147,13 +201,23 @@ fn main() {
                                 p_key_was_pressed = true;
                                 println!("Game Paused: {}", paused);
                             }
+                            if keycode == KeyCode::KeyT && !repeat {
+                                weather.cycle();
+                                println!("Weather: {}", weather.kind().name());
+                            }
+                            if keycode == KeyCode::KeyG && !repeat {
+                                player.glider = !player.glider;
+                                println!("Glider: {}", player.glider);
+                            }
                             if game_over && keycode == KeyCode::KeyR {
                                 game_over = false;
-                                player.pos = glam::Vec3::new(0.0, 40.0, 0.0);
+                                player.pos = start_pos;
                                 player.yaw = 0.0_f32.to_radians();
                                 player.pitch = 0.0;
                                 player.roll = 0.0;
                                 player.speed = 25.0;
+                                player.health = 1.0;
+                                player.stunned = 0.0;
                                 paused = false; 
                             }
                         }
@@ -174,35 +238,64 @@ fn main() {
                         total_time_elapsed += dt;
                         // Step Universe (1440x speedup: 1 min = 1 day)
                         universe.step(dt as f64 * 1440.0);
+                        weather.update(dt);
+                        precipitation.update(dt, &weather, player.pos);
+                        if lightning.update(dt, &weather, player.pos) && !game_over {
+                            player.health -= LIGHTNING_DAMAGE;
+                            player.stunned = LIGHTNING_STUN;
+                            // Knocked sideways and nose-down
+                            player.roll += 1.2;
+                            player.pitch -= 0.3;
+                            println!("Struck by lightning! Hull at {:.0}%", player.health.max(0.0) * 100.0);
+                            if player.health <= 0.0 { game_over = true; println!("CRASH!"); }
+                        }
                     }
                     let total_time = total_time_elapsed; 
                     
                     // Get Celestial positions from Physics Universe
                     let (sun_dir, moon_dir) = universe.get_sky_state();
+                    let mut palette = SkyPalette::new(sun_dir, moon_dir, player.pos.y, weather.current.overcast());
+                    palette.add_lightning(lightning.flash());
+                    let cloud_density = map_clouds(player.pos, &weather.clouds());
+                    let wind = wind_at(player.pos, &weather.current, total_time, |x, z| chunk_cache.building_at(x, z));
+                    let lift = thermal_lift(player.pos, solar_heating(sun_dir, weather.current.overcast()), weather.current.cloud_bottom);
+                    let mut velocity = glam::Vec3::ZERO;
 
                     if !game_over && !paused { 
                         let turn_speed = 2.0 * dt;
                         let mut target_roll = 0.0;
+                        // A lightning strike knocks out the controls for a moment
+                        player.stunned = (player.stunned - dt).max(0.0);
+                        let stunned = player.stunned > 0.0;
+                        let pressed = |key: KeyCode| !stunned && keys_pressed.contains(&key);
                         
-                        if keys_pressed.contains(&KeyCode::ArrowLeft) {
+                        if pressed(KeyCode::ArrowLeft) {
                             player.yaw -= turn_speed;
                             target_roll = -45.0_f32.to_radians(); 
-                        } else if keys_pressed.contains(&KeyCode::ArrowRight) {
+                        } else if pressed(KeyCode::ArrowRight) {
                             player.yaw += turn_speed;
                             target_roll = 45.0_f32.to_radians(); 
                         }
 
                         let pitch_speed = 1.5 * dt;
-                        if keys_pressed.contains(&KeyCode::ArrowUp) { player.pitch += pitch_speed; } 
-                        else if keys_pressed.contains(&KeyCode::ArrowDown) { player.pitch -= pitch_speed; }
+                        if pressed(KeyCode::ArrowUp) { player.pitch += pitch_speed; } 
+                        else if pressed(KeyCode::ArrowDown) { player.pitch -= pitch_speed; }
+                        // Buffeting inside clouds, in the wake of towers, and while the plane is stunned
+                        let shake = turbulence(cloud_density.max(wind.turbulence).max(player.stunned.min(1.0)), total_time);
+                        player.pitch += shake.x * dt;
+                        player.roll += shake.y * dt;
                         player.pitch = player.pitch.clamp(-80.0_f32.to_radians(), 80.0_f32.to_radians());
 
-                        if keys_pressed.contains(&KeyCode::KeyW) { player.speed += 20.0 * dt; } 
-                        else if keys_pressed.contains(&KeyCode::KeyS) { player.speed -= 20.0 * dt; }
-                        player.speed = player.speed.clamp(10.0, 100.0);
+                        if !player.glider {
+                            if pressed(KeyCode::KeyW) { player.speed += 20.0 * dt; } 
+                            else if pressed(KeyCode::KeyS) { player.speed -= 20.0 * dt; }
+                            player.speed = player.speed.clamp(10.0, 100.0);
+                        }
 
-                        let roll_lerp_speed = 3.0 * dt;
-                        player.roll = player.roll + (target_roll - player.roll) * roll_lerp_speed;
+                        if !stunned {
+                            let roll_lerp_speed = 3.0 * dt;
+                            player.roll = player.roll + (target_roll - player.roll) * roll_lerp_speed;
+                        }
 
                         let direction = glam::Vec3::new(
                             player.yaw.cos() * player.pitch.cos(),
@@ -210,13 +303,31 @@ fn main() {
                             player.yaw.sin() * player.pitch.cos()
                         ).normalize();
 
-                        player.pos += direction * player.speed * dt;
-                        if check_collision(player.pos) { game_over = true; println!("CRASH!"); }
+                        if player.glider {
+                            // Diving trades height for speed, drag bleeds it off
+                            player.speed += (-GRAVITY * direction.y - GLIDER_DRAG * player.speed * player.speed) * dt;
+                            player.speed = player.speed.clamp(1.0, 100.0);
+                            if player.speed < STALL_SPEED {
+                                player.pitch -= STALL_PITCH_RATE * dt;
+                            }
+                        }
+
+                        // The plane flies through moving air, so the wind and thermals carry it along
+                        velocity = direction * player.speed + wind.velocity + glam::Vec3::Y * lift;
+                        player.pos += velocity * dt;
+                        match check_collision(player.pos, |x, z| chunk_cache.building_at(x, z)) {
+                            Some(Contact::Water) => { game_over = true; println!("SPLASH! Ditched in the water."); }
+                            Some(_) => { game_over = true; println!("CRASH!"); }
+                            None => (),
+                        }
                     }
 
                     // --- Render ---
                     unsafe {
-                        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+                        sky_shader.poll();
+                        scene_shader.poll();
+                        let sky_program = sky_shader.program();
+                        let scene_program = scene_shader.program();
 
                         // Calculate Camera Matrices
                         let front = glam::Vec3::new(
@@ -229,78 +340,109 @@ fn main() {
                         let camera_up = roll_quat * right.cross(front).normalize();
                         
                         let view = glam::Mat4::look_at_rh(player.pos, player.pos + front, camera_up);
-                        let projection = glam::Mat4::perspective_rh_gl(60.0_f32.to_radians(), window.inner_size().width as f32 / window.inner_size().height as f32, 0.1, 1000.0);
+                        let fov_y = 60.0_f32.to_radians();
+                        let aspect = window.inner_size().width as f32 / window.inner_size().height as f32;
+                        let projection = glam::Mat4::perspective_rh_gl(fov_y, aspect, 0.5, 5000.0);
+
+                        let center_chunk = chunk_coord_at(player.pos);
+                        chunk_cache.update(center_chunk);
+
+                        // 0. Shadow maps for the sun and moon
+                        let shadow_camera = ShadowCamera { position: player.pos, view, fov_y, aspect, near: 0.5 };
+                        shadows.render(&chunk_cache, center_chunk, &shadow_camera, sun_dir, moon_dir);
+
+                        hdr.begin();
+                        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
 
                         // 1. Draw Skybox
                         gl::Disable(gl::DEPTH_TEST);
                         gl::DepthMask(gl::FALSE);
-                        gl::UseProgram(sky_program);
-                        
-                        let s_view_loc = gl::GetUniformLocation(sky_program, CStr::from_bytes_with_nul(b"view\0").unwrap().as_ptr());
-                        let s_proj_loc = gl::GetUniformLocation(sky_program, CStr::from_bytes_with_nul(b"projection\0").unwrap().as_ptr());
-                        let s_sun_loc = gl::GetUniformLocation(sky_program, CStr::from_bytes_with_nul(b"uSunDir\0").unwrap().as_ptr());
-                        let s_moon_loc = gl::GetUniformLocation(sky_program, CStr::from_bytes_with_nul(b"uMoonDir\0").unwrap().as_ptr());
-                        let s_time_loc = gl::GetUniformLocation(sky_program, CStr::from_bytes_with_nul(b"uTime\0").unwrap().as_ptr());
-                        let s_cam_loc = gl::GetUniformLocation(sky_program, CStr::from_bytes_with_nul(b"uCameraPos\0").unwrap().as_ptr());
-
-                        gl::UniformMatrix4fv(s_view_loc, 1, gl::FALSE, &view.to_cols_array()[0]);
-                        gl::UniformMatrix4fv(s_proj_loc, 1, gl::FALSE, &projection.to_cols_array()[0]);
-                        gl::Uniform3f(s_sun_loc, sun_dir.x, sun_dir.y, sun_dir.z);
-                        gl::Uniform3f(s_moon_loc, moon_dir.x, moon_dir.y, moon_dir.z);
-                        gl::Uniform1f(s_time_loc, total_time);
-                        gl::Uniform3f(s_cam_loc, player.pos.x, player.pos.y, player.pos.z);
+                        sky_program.bind();
+                        sky_program.set_mat4("view", &view);
+                        sky_program.set_mat4("projection", &projection);
+                        sky_program.set_vec3("uSunDir", sun_dir);
+                        sky_program.set_vec3("uMoonDir", moon_dir);
+                        sky_program.set_f32("uTime", total_time);
+                        sky_program.set_vec3("uCameraPos", player.pos);
+                        sky_program.set_f32("uSeaLevel", SEA_LEVEL);
+                        sky_program.set_f32("uDrawDistance", draw_distance);
+                        weather.apply(sky_program);
+                        palette.apply_sky(sky_program);
                         
-                        gl::BindVertexArray(vao);
-                        gl::DrawArrays(gl::TRIANGLES, 0, 36);
+                        cube.draw();
 
                         // 2. Draw Scene
                         gl::Enable(gl::DEPTH_TEST); // Re-enable depth test
                         gl::DepthMask(gl::TRUE);
-                        gl::UseProgram(scene_program);
-
-                        let m_model_loc = gl::GetUniformLocation(scene_program, CStr::from_bytes_with_nul(b"model\0").unwrap().as_ptr());
-                        let m_view_loc = gl::GetUniformLocation(scene_program, CStr::from_bytes_with_nul(b"view\0").unwrap().as_ptr());
-                        let m_proj_loc = gl::GetUniformLocation(scene_program, CStr::from_bytes_with_nul(b"projection\0").unwrap().as_ptr());
-                        let m_color_loc = gl::GetUniformLocation(scene_program, CStr::from_bytes_with_nul(b"uBaseColor\0").unwrap().as_ptr());
-                        let m_h_loc = gl::GetUniformLocation(scene_program, CStr::from_bytes_with_nul(b"uMaxHeight\0").unwrap().as_ptr());
-                        let m_sun_loc = gl::GetUniformLocation(scene_program, CStr::from_bytes_with_nul(b"uSunDir\0").unwrap().as_ptr());
-                        let m_moon_loc = gl::GetUniformLocation(scene_program, CStr::from_bytes_with_nul(b"uMoonDir\0").unwrap().as_ptr());
-                        let m_cam_loc = gl::GetUniformLocation(scene_program, CStr::from_bytes_with_nul(b"uCameraPos\0").unwrap().as_ptr());
-
-                        gl::UniformMatrix4fv(m_view_loc, 1, gl::FALSE, &view.to_cols_array()[0]);
-                        gl::UniformMatrix4fv(m_proj_loc, 1, gl::FALSE, &projection.to_cols_array()[0]);
-                        gl::Uniform1f(m_h_loc, MAX_BUILDING_HEIGHT);
-                        gl::Uniform3f(m_sun_loc, sun_dir.x, sun_dir.y, sun_dir.z);
-                        gl::Uniform3f(m_moon_loc, moon_dir.x, moon_dir.y, moon_dir.z);
-                        gl::Uniform3f(m_cam_loc, player.pos.x, player.pos.y, player.pos.z);
+                        scene_program.bind();
+                        scene_program.set_mat4("view", &view);
+                        scene_program.set_mat4("projection", &projection);
+                        scene_program.set_f32("uMaxHeight", MAX_BUILDING_HEIGHT);
+                        scene_program.set_f32("uSeaLevel", SEA_LEVEL);
+                        scene_program.set_vec3("uSunDir", sun_dir);
+                        scene_program.set_vec3("uMoonDir", moon_dir);
+                        scene_program.set_vec3("uCameraPos", player.pos);
+                        scene_program.set_f32("uCloudFog", cloud_density * CLOUD_FOG_EXTINCTION);
+                        weather.apply(scene_program);
+                        scene_program.set_f32("uWetness", weather.wetness);
+                        palette.apply_scene(scene_program);
+                        shadows.apply(scene_program, 0);
 
                         // Render Buildings
-                        let grid_pos_x = (player.pos.x / GRID_SPACING).floor() as i32;
-                        let grid_pos_z = (player.pos.z / GRID_SPACING).floor() as i32;
-                        let view_dist = 25; 
-
-                        for x in (grid_pos_x - view_dist)..=(grid_pos_x + view_dist) {
-                             for z in (grid_pos_z - view_dist)..=(grid_pos_z + view_dist) {
-                                 if let Some((height, color)) = get_building_info(x, z) {
-                                     let world_x = x as f32 * GRID_SPACING;
-                                     let world_z = z as f32 * GRID_SPACING;
-                                     
-                                     let model = glam::Mat4::from_translation(glam::Vec3::new(world_x, (height / 2.0) + GROUND_LEVEL, world_z)) 
-                                               * glam::Mat4::from_scale(glam::Vec3::new(BUILDING_WIDTH, height, BUILDING_WIDTH));
-                                     
-                                     gl::UniformMatrix4fv(m_model_loc, 1, gl::FALSE, &model.to_cols_array()[0]);
-                                     gl::Uniform3f(m_color_loc, color.x, color.y, color.z);
-                                     gl::DrawArrays(gl::TRIANGLES, 0, 36);
-                                 }
-                             }
+                        let frustum = Frustum::from_matrix(projection * view);
+                        let mut stats = chunk_cache.draw(center_chunk, &frustum, player.pos, draw_distance);
+                        stats.draw_calls += shadows.draw_calls;
+
+                        // Average draw counts over a second in the title bar
+                        stats_accum += stats;
+                        stats_frames += 1;
+                        stats_timer += dt;
+                        if stats_timer >= 1.0 {
+                            let n = stats_frames;
+                            window.set_title(&format!(
+                                "Arcade Flyer - {} fps | chunks {}/{} | buildings {}/{} | impostors {} | draws {} | {} | {} | visibility {} | hull {:.0}%{}",
+                                n,
+                                stats_accum.chunks_drawn / n,
+                                (stats_accum.chunks_drawn + stats_accum.chunks_culled) / n,
+                                stats_accum.buildings_drawn / n,
+                                (stats_accum.buildings_drawn + stats_accum.buildings_culled) / n,
+                                stats_accum.impostors_drawn / n,
+                                stats_accum.draw_calls / n,
+                                district_at((player.pos.x / GRID_SPACING).round() as i32, (player.pos.z / GRID_SPACING).round() as i32).name(),
+                                weather.kind().name(),
+                                match visibility(cloud_density).min(weather.fog_visibility(player.pos.y)) {
+                                    v if v.is_finite() => format!("{:.0}m", v),
+                                    _ => "clear".to_string(),
+                                },
+                                player.health.max(0.0) * 100.0,
+                                if player.glider { format!(" | glider {:+.1}m/s", velocity.y) } else { String::new() },
+                            ));
+                            stats_accum = DrawStats::default();
+                            stats_frames = 0;
+                            stats_timer = 0.0;
                         }
 
-                        // Ground
-                        let ground_color = glam::Vec3::new(0.9, 0.8, 0.85); 
-                        gl::Uniform3f(m_color_loc, ground_color.x, ground_color.y, ground_color.z);
-                        let model = glam::Mat4::from_translation(glam::Vec3::new(player.pos.x, GROUND_LEVEL - 1.0, player.pos.z)) * glam::Mat4::from_scale(glam::Vec3::new(800.0, 1.0, 800.0));
-                        gl::UniformMatrix4fv(m_model_loc, 1, gl::FALSE, &model.to_cols_array()[0]);
-                        gl::DrawArrays(gl::TRIANGLES, 0, 36);
+                        // Sea, river and harbours, over the ground the terrain dips into
+                        water.draw(&(projection * view), &WaterFrame {
+                            camera: player.pos,
+                            draw_distance,
+                            time: total_time,
+                            sun_dir,
+                            moon_dir,
+                            cloud_fog: cloud_density * CLOUD_FOG_EXTINCTION,
+                        }, &weather, &palette);
+
+                        // 3. Lightning, then rain and snow over the opaque scene
+                        lightning.draw(&(projection * view));
+                        precipitation.draw(&(projection * view), player.pos, velocity, total_time, &weather, &palette);
+
+                        hdr.finish(dt, post.input());
+
+                        // Motion blur streaks away from the point straight ahead
+                        let ahead = projection * view * (player.pos + front * 100.0).extend(1.0);
+                        let focus = glam::Vec2::new(ahead.x, ahead.y) / ahead.w * 0.5 + 0.5;
+                        let speed = ((player.speed - 25.0) / 75.0).clamp(0.0, 1.0);
+                        post.apply(&PostFrame { focus, speed });
                     }
                     surface.swap_buffers(&gl_context).unwrap();
                 }
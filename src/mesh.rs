@@ -0,0 +1,259 @@
+use glam::{Vec2, Vec3, Vec4};
+
+// Vertex layout (matches SCENE_VERTEX_SHADER). Locations 1..=5 and 9 are taken by
+// the per-instance attributes in instancing.rs.
+//   location 0 : position
+//   location 6 : normal
+//   location 7 : uv, in mesh units along the tangent and bitangent
+//   location 8 : tangent (xyz), with the bitangent's handedness in w
+
+pub const MESH_POSITION_LOCATION: u32 = 0;
+pub const MESH_NORMAL_LOCATION: u32 = 6;
+pub const MESH_UV_LOCATION: u32 = 7;
+pub const MESH_TANGENT_LOCATION: u32 = 8;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    pub tangent: [f32; 4],
+}
+
+// Indexed triangle geometry on the CPU, put together a face at a time
+#[derive(Clone, Debug, Default)]
+pub struct MeshBuilder {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+}
+
+impl MeshBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns the new vertex's index
+    pub fn vertex(&mut self, position: Vec3, normal: Vec3, uv: Vec2, tangent: Vec4) -> u32 {
+        self.vertices.push(Vertex {
+            position: position.to_array(),
+            normal: normal.to_array(),
+            uv: uv.to_array(),
+            tangent: tangent.to_array(),
+        });
+        self.vertices.len() as u32 - 1
+    }
+
+    // Counter-clockwise seen from the front
+    pub fn triangle(&mut self, a: u32, b: u32, c: u32) -> &mut Self {
+        self.indices.extend([a, b, c]);
+        self
+    }
+
+    // The parallelogram from `corner` along `u` and `v`, facing u x v. UVs
+    // run along the edges in mesh units, so a stretched instance stretches
+    // its texture space with it.
+    pub fn quad(&mut self, corner: Vec3, u: Vec3, v: Vec3) -> &mut Self {
+        let normal = u.cross(v).normalize();
+        let tangent = u.normalize().extend(normal.cross(u).dot(v).signum());
+        let (w, h) = (u.length(), v.length());
+        let a = self.vertex(corner, normal, Vec2::ZERO, tangent);
+        let b = self.vertex(corner + u, normal, Vec2::new(w, 0.0), tangent);
+        let c = self.vertex(corner + u + v, normal, Vec2::new(w, h), tangent);
+        let d = self.vertex(corner + v, normal, Vec2::new(0.0, h), tangent);
+        self.triangle(a, b, c).triangle(a, c, d)
+    }
+
+    // Axis-aligned box. Walls run their u along the ground and v straight
+    // up, so facades read bottom to top.
+    pub fn cuboid(&mut self, min: Vec3, max: Vec3) -> &mut Self {
+        let size = max - min;
+        let (x, y, z) = (Vec3::X * size.x, Vec3::Y * size.y, Vec3::Z * size.z);
+        self.quad(Vec3::new(max.x, min.y, max.z), -z, y)
+            .quad(min, z, y)
+            .quad(Vec3::new(min.x, min.y, max.z), x, y)
+            .quad(Vec3::new(max.x, min.y, min.z), -x, y)
+            .quad(Vec3::new(min.x, max.y, max.z), x, -z)
+            .quad(min, x, z)
+    }
+
+    // Upright round column around the y axis from `bottom` to `top`, tapering
+    // from one radius to the other: a cylinder, or a cone with a top radius
+    // of 0. Walls are smooth, with u running around the circumference.
+    pub fn frustum(&mut self, bottom: f32, top: f32, bottom_radius: f32, top_radius: f32, segments: u32) -> &mut Self {
+        let height = top - bottom;
+        let slope = (bottom_radius - top_radius) / height;
+        let slant = (height * height + (bottom_radius - top_radius).powi(2)).sqrt();
+        let first = self.vertices.len() as u32;
+        for i in 0..=segments {
+            let angle = i as f32 / segments as f32 * std::f32::consts::TAU;
+            let (sin, cos) = angle.sin_cos();
+            let out = Vec3::new(cos, 0.0, -sin);
+            let normal = (out + Vec3::Y * slope).normalize();
+            let tangent = Vec3::new(-sin, 0.0, -cos).extend(1.0);
+            let u = angle * bottom_radius;
+            self.vertex(out * bottom_radius + Vec3::Y * bottom, normal, Vec2::new(u, 0.0), tangent);
+            self.vertex(out * top_radius + Vec3::Y * top, normal, Vec2::new(u, slant), tangent);
+        }
+        for i in 0..segments {
+            let (a, b) = (first + i * 2, first + i * 2 + 2);
+            // A cone's point would make half of these empty
+            if bottom_radius > 0.0 {
+                self.triangle(a, b, b + 1);
+            }
+            if top_radius > 0.0 {
+                self.triangle(a, b + 1, a + 1);
+            }
+        }
+        self.disc(top, top_radius, Vec3::Y, segments).disc(bottom, bottom_radius, -Vec3::Y, segments)
+    }
+
+    // Flat cap facing straight up or down, UVs mapped from x and z
+    fn disc(&mut self, y: f32, radius: f32, normal: Vec3, segments: u32) -> &mut Self {
+        if radius <= 0.0 {
+            return self;
+        }
+        let tangent = Vec3::X.extend(normal.y.signum());
+        let point = |angle: f32| Vec3::new(angle.cos() * radius, y, -angle.sin() * radius);
+        let center = self.vertex(Vec3::new(0.0, y, 0.0), normal, Vec2::ZERO, tangent);
+        for i in 0..segments {
+            let (a0, a1) = (i as f32, (i + 1) as f32);
+            let (p0, p1) = [a0, a1].map(|a| point(a / segments as f32 * std::f32::consts::TAU)).into();
+            let v0 = self.vertex(p0, normal, Vec2::new(p0.x, -p0.z), tangent);
+            let v1 = self.vertex(p1, normal, Vec2::new(p1.x, -p1.z), tangent);
+            if normal.y > 0.0 {
+                self.triangle(center, v0, v1);
+            } else {
+                self.triangle(center, v1, v0);
+            }
+        }
+        self
+    }
+
+    pub unsafe fn build(&self) -> Mesh {
+        Mesh::new(&self.vertices, &self.indices)
+    }
+}
+
+// The unit cube centred on the origin that buildings, impostors and the sea
+// are scaled from
+pub fn unit_cube() -> MeshBuilder {
+    let mut builder = MeshBuilder::new();
+    builder.cuboid(Vec3::splat(-0.5), Vec3::splat(0.5));
+    builder
+}
+
+// Round shapes of unit diameter and height centred on the origin, scaled per
+// instance like the cube
+pub fn unit_cylinder() -> MeshBuilder {
+    let mut builder = MeshBuilder::new();
+    builder.frustum(-0.5, 0.5, 0.5, 0.5, 24);
+    builder
+}
+
+pub fn unit_cone() -> MeshBuilder {
+    let mut builder = MeshBuilder::new();
+    builder.frustum(-0.5, 0.5, 0.5, 0.0, 12);
+    builder
+}
+
+// Vertex and index buffers on the GPU, with a VAO of their own for drawing
+// a single copy. InstanceBuffer pairs the same buffers with instance data.
+pub struct Mesh {
+    vao: u32,
+    vbo: u32,
+    ebo: u32,
+    index_count: i32,
+}
+
+impl Mesh {
+    pub unsafe fn new(vertices: &[Vertex], indices: &[u32]) -> Self {
+        let (mut vao, mut vbo, mut ebo) = (0, 0, 0);
+        gl::GenVertexArrays(1, &mut vao);
+        gl::GenBuffers(1, &mut vbo);
+        gl::GenBuffers(1, &mut ebo);
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
+        gl::BufferData(gl::ARRAY_BUFFER, std::mem::size_of_val(vertices) as isize, vertices.as_ptr() as *const _, gl::STATIC_DRAW);
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+        gl::BufferData(gl::ELEMENT_ARRAY_BUFFER, std::mem::size_of_val(indices) as isize, indices.as_ptr() as *const _, gl::STATIC_DRAW);
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
+
+        let mesh = Self { vao, vbo, ebo, index_count: indices.len() as i32 };
+        gl::BindVertexArray(vao);
+        mesh.bind_attributes();
+        gl::BindVertexArray(0);
+        mesh
+    }
+
+    pub fn index_count(&self) -> i32 {
+        self.index_count
+    }
+
+    // Points the bound VAO's mesh attributes and index buffer at this mesh
+    pub unsafe fn bind_attributes(&self) {
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.vbo);
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.ebo);
+        let stride = std::mem::size_of::<Vertex>() as i32;
+        let attributes = [
+            (MESH_POSITION_LOCATION, 3, std::mem::offset_of!(Vertex, position)),
+            (MESH_NORMAL_LOCATION, 3, std::mem::offset_of!(Vertex, normal)),
+            (MESH_UV_LOCATION, 2, std::mem::offset_of!(Vertex, uv)),
+            (MESH_TANGENT_LOCATION, 4, std::mem::offset_of!(Vertex, tangent)),
+        ];
+        for (location, size, offset) in attributes {
+            gl::VertexAttribPointer(location, size, gl::FLOAT, gl::FALSE, stride, offset as *const _);
+            gl::EnableVertexAttribArray(location);
+        }
+    }
+
+    pub unsafe fn draw(&self) {
+        gl::BindVertexArray(self.vao);
+        gl::DrawElements(gl::TRIANGLES, self.index_count, gl::UNSIGNED_INT, std::ptr::null());
+    }
+}
+
+impl Drop for Mesh {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.vbo);
+            gl::DeleteBuffers(1, &self.ebo);
+            gl::DeleteVertexArrays(1, &self.vao);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn faces_point_outwards() {
+        assert_eq!((unit_cube().vertices.len(), unit_cube().indices.len()), (24, 36));
+        for (mesh, tolerance) in [(unit_cube(), 1e-6), (unit_cylinder(), 0.15), (unit_cone(), 0.4)] {
+            let position = |i: u32| Vec3::from(mesh.vertices[i as usize].position);
+            for tri in mesh.indices.chunks(3) {
+                let (a, b, c) = (position(tri[0]), position(tri[1]), position(tri[2]));
+                let winding = (b - a).cross(c - a).normalize();
+                // Smooth normals lean away from the flat face by up to half a segment
+                let v = mesh.vertices[tri[0] as usize];
+                assert!(winding.abs_diff_eq(Vec3::from(v.normal), tolerance), "{:?} vs {:?}", winding, v.normal);
+                // Outward: the face's centre lies along its normal
+                assert!(((a + b + c) / 3.0).dot(winding) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn uvs_follow_the_tangent_frame() {
+        let mut builder = MeshBuilder::new();
+        builder.cuboid(Vec3::new(0.0, 0.0, 0.0), Vec3::new(5.0, 30.0, 5.0));
+        for v in &builder.vertices {
+            let (normal, tangent) = (Vec3::from(v.normal), Vec3::from_slice(&v.tangent[..3]));
+            assert!(normal.dot(tangent).abs() < 1e-6);
+            if normal.y == 0.0 {
+                // Walls: v is the height above the base
+                assert_eq!(v.uv[1], v.position[1]);
+            }
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use glam::Vec3;
+
+// Smooth value noise for everything generated from a field rather than a
+// per-cell hash: the ground, the districts, the wind and the clouds. It is
+// the noise CLOUDS_GLSL uses, bit for bit, and clouds.rs re-exports it for
+// its port of that shader. Give the clouds their own copy before retuning
+// the shader's, or the terrain and the city move with them.
+
+// GLSL's fract, which floors (Rust's rounds towards zero)
+fn fract(x: f32) -> f32 {
+    x - x.floor()
+}
+
+pub fn mix(a: f32, b: f32, t: f32) -> f32 {
+    a * (1.0 - t) + b * t
+}
+
+// The literal is the shader's, not 1/pi, so both round to the same float
+#[allow(clippy::approx_constant)]
+fn hash(p: Vec3) -> f32 {
+    let p = p * 0.3183099 + Vec3::splat(0.1);
+    let p = (p - p.floor()) * 17.0;
+    fract(p.x * p.y * p.z * (p.x + p.y + p.z))
+}
+
+pub fn noise(x: Vec3) -> f32 {
+    let i = x.floor();
+    let f = x - i;
+    let f = f * f * (Vec3::splat(3.0) - 2.0 * f);
+    let h = |dx: f32, dy: f32, dz: f32| hash(i + Vec3::new(dx, dy, dz));
+    mix(
+        mix(mix(h(0.0, 0.0, 0.0), h(1.0, 0.0, 0.0), f.x), mix(h(0.0, 1.0, 0.0), h(1.0, 1.0, 0.0), f.x), f.y),
+        mix(mix(h(0.0, 0.0, 1.0), h(1.0, 0.0, 1.0), f.x), mix(h(0.0, 1.0, 1.0), h(1.0, 1.0, 1.0), f.x), f.y),
+        f.z,
+    )
+}
+
+pub fn fbm(mut x: Vec3) -> f32 {
+    let mut v = 0.0;
+    let mut a = 0.5;
+    for _ in 0..3 {
+        v += a * noise(x);
+        x = x * 2.0 + Vec3::splat(100.0);
+        a *= 0.5;
+    }
+    v
+}
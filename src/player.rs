@@ -1,9 +1,13 @@
-use glam;
-
 pub struct Player {
     pub pos: glam::Vec3,
     pub yaw: f32,
     pub pitch: f32,
     pub roll: f32,
     pub speed: f32,
+    // 1 is undamaged; the plane goes down at 0
+    pub health: f32,
+    // Seconds left before the controls respond again
+    pub stunned: f32,
+    // No engine: gravity, drag and rising air decide the speed
+    pub glider: bool,
 }
@@ -0,0 +1,371 @@
+use std::fs;
+use std::path::Path;
+
+use glam::{Vec2, Vec3};
+
+use crate::gl_utils::{Framebuffer, Texture, TextureFormat};
+use crate::hot_reload::ReloadableProgram;
+use crate::settings::PostSettings;
+use crate::shaders::{
+    COLOR_GRADING_FRAGMENT_SHADER, FXAA_FRAGMENT_SHADER, MOTION_BLUR_FRAGMENT_SHADER, POST_VERTEX_SHADER,
+    VIGNETTE_FRAGMENT_SHADER,
+};
+
+// Resolution of the built-in grading LUT
+const DEFAULT_LUT_SIZE: usize = 32;
+
+// Passes run in this order. FXAA goes last so it sees the final edges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PostPass {
+    MotionBlur,
+    ColorGrading,
+    Vignette,
+    Fxaa,
+}
+
+// Per-frame inputs the passes need besides the image
+#[derive(Clone, Copy, Debug)]
+pub struct PostFrame {
+    // Screen position (0..1) the camera is flying towards
+    pub focus: Vec2,
+    // 0 at cruising speed, 1 flat out
+    pub speed: f32,
+}
+
+// A 3D colour lookup table flattened into a strip of `size` blue slices
+struct Lut {
+    texture: Texture,
+    size: usize,
+}
+
+impl Lut {
+    unsafe fn new(size: usize, colors: &[Vec3]) -> Self {
+        // .cube order is red fastest, then green, then blue. The strip puts
+        // blue slices side by side: x = r + b * size, y = g.
+        let mut pixels = vec![[0.0; 4]; size * size * size];
+        for (i, c) in colors.iter().enumerate() {
+            let (r, g, b) = (i % size, (i / size) % size, i / (size * size));
+            pixels[g * size * size + b * size + r] = [c.x, c.y, c.z, 1.0];
+        }
+        let texture = Texture::from_rgba((size * size) as i32, size as i32, TextureFormat::RGBA16F, &pixels);
+        Self { texture, size }
+    }
+
+    unsafe fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut max_width = 0;
+        gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_width);
+        let (size, colors) = parse_cube(&text, max_width as usize)?;
+        Ok(Self::new(size, &colors))
+    }
+
+    // Gentle filmic grade: a little contrast and saturation, cool shadows
+    // and warm highlights
+    unsafe fn builtin() -> Self {
+        let n = DEFAULT_LUT_SIZE;
+        let colors: Vec<Vec3> = (0..n * n * n).map(|i| {
+            let c = Vec3::new((i % n) as f32, ((i / n) % n) as f32, (i / (n * n)) as f32) / (n - 1) as f32;
+            let luma = c.dot(Vec3::new(0.2126, 0.7152, 0.0722));
+            let saturated = Vec3::splat(luma) + (c - Vec3::splat(luma)) * 1.1;
+            let contrasted = saturated.lerp(saturated * saturated * (Vec3::splat(3.0) - 2.0 * saturated), 0.25);
+            let tint = Vec3::new(0.97, 1.0, 1.04).lerp(Vec3::new(1.04, 1.0, 0.95), luma);
+            (contrasted * tint).clamp(Vec3::ZERO, Vec3::ONE)
+        }).collect();
+        Self::new(n, &colors)
+    }
+}
+
+// Adobe .cube 3D LUT: `LUT_3D_SIZE n` followed by n^3 "r g b" lines in 0..1.
+// The grading shader interpolates between at least two entries per axis, and
+// the n * n strip has to fit in a texture `max_width` texels wide.
+fn parse_cube(text: &str, max_width: usize) -> Result<(usize, Vec<Vec3>), String> {
+    let mut size = None;
+    let mut colors = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let first = words.next().unwrap();
+        if first == "LUT_3D_SIZE" {
+            let n = words.next().and_then(|w| w.parse::<usize>().ok()).ok_or("bad LUT_3D_SIZE")?;
+            if n < 2 {
+                return Err(format!("LUT_3D_SIZE {} is too small", n));
+            }
+            if n.checked_mul(n).is_none_or(|width| width > max_width) {
+                return Err(format!("LUT_3D_SIZE {} is too large for this GPU", n));
+            }
+            size = Some(n);
+        } else if first.chars().next().is_some_and(|c| c.is_ascii_alphabetic()) {
+            // TITLE, DOMAIN_MIN/MAX and other keywords
+            continue;
+        } else {
+            let values: Vec<f32> = line.split_whitespace().filter_map(|w| w.parse().ok()).collect();
+            if values.len() != 3 {
+                return Err(format!("bad LUT entry `{}`", line));
+            }
+            colors.push(Vec3::new(values[0], values[1], values[2]));
+        }
+    }
+    let size = size.ok_or("missing LUT_3D_SIZE")?;
+    if colors.len() != size * size * size {
+        return Err(format!("expected {} LUT entries, found {}", size * size * size, colors.len()));
+    }
+    Ok((size, colors))
+}
+
+// Display-space effects applied after tonemapping. Passes ping-pong between
+// two targets; the last one draws straight to the window.
+pub struct PostChain {
+    pub settings: PostSettings,
+    width: i32,
+    height: i32,
+    targets: [Framebuffer; 2],
+    lut: Lut,
+    vao: u32,
+    motion_blur: ReloadableProgram,
+    color_grading: ReloadableProgram,
+    vignette: ReloadableProgram,
+    fxaa: ReloadableProgram,
+}
+
+impl PostChain {
+    pub unsafe fn new(width: i32, height: i32, settings: PostSettings) -> Self {
+        let lut = match &settings.lut {
+            Some(path) => Lut::load(path).unwrap_or_else(|e| {
+                eprintln!("Could not load LUT {}: {}, using the built-in grade", path.display(), e);
+                Lut::builtin()
+            }),
+            None => Lut::builtin(),
+        };
+        let width = width.max(1);
+        let height = height.max(1);
+        let mut vao = 0;
+        gl::GenVertexArrays(1, &mut vao);
+
+        Self {
+            settings,
+            width,
+            height,
+            targets: Self::create_targets(width, height),
+            lut,
+            vao,
+            motion_blur: ReloadableProgram::new("motion_blur", POST_VERTEX_SHADER, MOTION_BLUR_FRAGMENT_SHADER),
+            color_grading: ReloadableProgram::new("color_grading", POST_VERTEX_SHADER, COLOR_GRADING_FRAGMENT_SHADER),
+            vignette: ReloadableProgram::new("vignette", POST_VERTEX_SHADER, VIGNETTE_FRAGMENT_SHADER),
+            fxaa: ReloadableProgram::new("fxaa", POST_VERTEX_SHADER, FXAA_FRAGMENT_SHADER),
+        }
+    }
+
+    unsafe fn create_targets(width: i32, height: i32) -> [Framebuffer; 2] {
+        [(); 2].map(|_| Framebuffer::new(width, height, TextureFormat::RGBA8, false).expect("post-process target"))
+    }
+
+    pub unsafe fn resize(&mut self, width: i32, height: i32) {
+        let width = width.max(1);
+        let height = height.max(1);
+        if (width, height) != (self.width, self.height) {
+            self.width = width;
+            self.height = height;
+            self.targets = Self::create_targets(width, height);
+        }
+    }
+
+    pub fn passes(&self) -> Vec<PostPass> {
+        let s = &self.settings;
+        [
+            (PostPass::MotionBlur, s.motion_blur > 0.0),
+            (PostPass::ColorGrading, s.color_grading > 0.0),
+            (PostPass::Vignette, s.vignette > 0.0),
+            (PostPass::Fxaa, s.fxaa),
+        ].into_iter().filter(|&(_, on)| on).map(|(pass, _)| pass).collect()
+    }
+
+    // Where the tonemapper should draw: the chain's first target, or the
+    // window itself when every pass is switched off
+    pub fn input(&self) -> Option<&Framebuffer> {
+        if self.passes().is_empty() { None } else { Some(&self.targets[0]) }
+    }
+
+    pub unsafe fn apply(&mut self, frame: &PostFrame) {
+        for program in [&mut self.motion_blur, &mut self.color_grading, &mut self.vignette, &mut self.fxaa] {
+            program.poll();
+        }
+        let passes = self.passes();
+        for (i, &pass) in passes.iter().enumerate() {
+            let source = self.targets[i % 2].color();
+            let target = if i + 1 == passes.len() { None } else { Some(&self.targets[(i + 1) % 2]) };
+            self.run_pass(pass, source, target, frame);
+        }
+    }
+
+    // Runs one pass from `source` into `target` (the window if None)
+    pub unsafe fn run_pass(&self, pass: PostPass, source: &Texture, target: Option<&Framebuffer>, frame: &PostFrame) {
+        match target {
+            Some(fb) => fb.bind(),
+            None => Framebuffer::bind_default(self.width, self.height),
+        }
+        gl::Disable(gl::DEPTH_TEST);
+        gl::BindVertexArray(self.vao);
+        source.bind(0);
+
+        let settings = &self.settings;
+        let program = match pass {
+            PostPass::MotionBlur => {
+                let program = self.motion_blur.program();
+                program.bind();
+                program.set_vec2("uFocus", frame.focus);
+                // A tenth of the way to the focus point at full speed and strength
+                program.set_f32("uStrength", settings.motion_blur * frame.speed.clamp(0.0, 1.0) * 0.1);
+                program
+            }
+            PostPass::ColorGrading => {
+                let program = self.color_grading.program();
+                program.bind();
+                self.lut.texture.bind(1);
+                program.set_i32("uLut", 1);
+                program.set_f32("uLutSize", self.lut.size as f32);
+                program.set_f32("uIntensity", settings.color_grading.min(1.0));
+                program
+            }
+            PostPass::Vignette => {
+                let program = self.vignette.program();
+                program.bind();
+                program.set_f32("uStrength", settings.vignette.min(1.0));
+                program.set_f32("uAspect", source.width as f32 / source.height as f32);
+                program
+            }
+            PostPass::Fxaa => {
+                let program = self.fxaa.program();
+                program.bind();
+                program.set_vec2("uTexelSize", Vec2::new(1.0 / source.width as f32, 1.0 / source.height as f32));
+                program
+            }
+        };
+        program.set_i32("uSource", 0);
+        gl::DrawArrays(gl::TRIANGLES, 0, 3);
+
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::Enable(gl::DEPTH_TEST);
+    }
+}
+
+impl Drop for PostChain {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteVertexArrays(1, &self.vao) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gl_utils::headless;
+
+    const SIZE: i32 = 64;
+
+    fn frame() -> PostFrame {
+        PostFrame { focus: Vec2::splat(0.5), speed: 1.0 }
+    }
+
+    // Source texture filled by `f(x, y)`
+    unsafe fn image(f: impl Fn(i32, i32) -> f32) -> Texture {
+        let pixels: Vec<[f32; 4]> = (0..SIZE * SIZE).map(|i| {
+            let v = f(i % SIZE, i / SIZE);
+            [v, v, v, 1.0]
+        }).collect();
+        Texture::from_rgba(SIZE, SIZE, TextureFormat::RGBA8, &pixels)
+    }
+
+    // Renders one pass into an offscreen target and reads back the red channel
+    unsafe fn render(chain: &PostChain, pass: PostPass, source: &Texture) -> Vec<f32> {
+        let target = Framebuffer::new(SIZE, SIZE, TextureFormat::RGBA8, false).unwrap();
+        chain.run_pass(pass, source, Some(&target), &frame());
+        target.bind();
+        let mut pixels = vec![0u8; (SIZE * SIZE * 4) as usize];
+        gl::ReadPixels(0, 0, SIZE, SIZE, gl::RGBA, gl::UNSIGNED_BYTE, pixels.as_mut_ptr() as *mut _);
+        pixels.chunks(4).map(|p| p[0] as f32 / 255.0).collect()
+    }
+
+    fn at(pixels: &[f32], x: i32, y: i32) -> f32 {
+        pixels[(y * SIZE + x) as usize]
+    }
+
+    unsafe fn chain(settings: PostSettings) -> PostChain {
+        PostChain::new(SIZE, SIZE, settings)
+    }
+
+    #[test]
+    fn vignette_darkens_corners_only() {
+        let Some(_gl) = headless::context() else { return };
+        unsafe {
+            let chain = chain(PostSettings { vignette: 0.5, ..Default::default() });
+            let out = render(&chain, PostPass::Vignette, &image(|_, _| 1.0));
+            assert!(at(&out, SIZE / 2, SIZE / 2) > 0.99);
+            assert!(at(&out, 0, 0) < 0.6);
+        }
+    }
+
+    #[test]
+    fn identity_lut_leaves_colours_alone() {
+        let Some(_gl) = headless::context() else { return };
+        unsafe {
+            let mut chain = chain(PostSettings::default());
+            let n = 16;
+            let identity: Vec<Vec3> = (0..n * n * n)
+                .map(|i| Vec3::new((i % n) as f32, ((i / n) % n) as f32, (i / (n * n)) as f32) / (n - 1) as f32)
+                .collect();
+            chain.lut = Lut::new(n, &identity);
+            let out = render(&chain, PostPass::ColorGrading, &image(|x, _| x as f32 / (SIZE - 1) as f32));
+            for x in 0..SIZE {
+                assert!((at(&out, x, 0) - x as f32 / (SIZE - 1) as f32).abs() < 2.0 / 255.0);
+            }
+        }
+    }
+
+    #[test]
+    fn fxaa_softens_a_hard_edge() {
+        let Some(_gl) = headless::context() else { return };
+        unsafe {
+            let chain = chain(PostSettings::default());
+            // Diagonal staircase
+            let out = render(&chain, PostPass::Fxaa, &image(|x, y| if x > y { 1.0 } else { 0.0 }));
+            let blended = out.iter().filter(|&&v| v > 0.05 && v < 0.95).count();
+            assert!(blended > 0);
+            // Flat areas away from the edge are untouched
+            assert_eq!(at(&out, SIZE - 1, 0), 1.0);
+            assert_eq!(at(&out, 0, SIZE - 1), 0.0);
+        }
+    }
+
+    #[test]
+    fn motion_blur_scales_with_speed() {
+        let Some(_gl) = headless::context() else { return };
+        unsafe {
+            let source = image(|x, _| if x % 2 == 0 { 1.0 } else { 0.0 });
+            let still = chain(PostSettings { motion_blur: 0.0, ..Default::default() });
+            let out = render(&still, PostPass::MotionBlur, &source);
+            assert_eq!(at(&out, 2, SIZE / 2), 1.0);
+
+            let fast = chain(PostSettings { motion_blur: 1.0, ..Default::default() });
+            let out = render(&fast, PostPass::MotionBlur, &source);
+            // Stripes far from the focus are smeared towards grey
+            assert!(at(&out, 2, SIZE / 2) < 0.9);
+        }
+    }
+
+    #[test]
+    fn parses_cube_files() {
+        let text = "TITLE \"test\"\n# comment\nLUT_3D_SIZE 2\n0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n";
+        let (size, colors) = parse_cube(text, 16384).unwrap();
+        assert_eq!(size, 2);
+        assert_eq!(colors[1], Vec3::X);
+        assert_eq!(colors[7], Vec3::ONE);
+        assert!(parse_cube("LUT_3D_SIZE 2\n0 0 0\n", 16384).is_err());
+        // Too few entries to interpolate between, however many follow
+        assert!(parse_cube("LUT_3D_SIZE 0\n", 16384).is_err());
+        assert!(parse_cube("LUT_3D_SIZE 1\n0.5 0.5 0.5\n", 16384).is_err());
+        // A strip wider than the GPU allows, rejected before reading entries
+        assert!(parse_cube(text, 3).is_err());
+        assert!(parse_cube("LUT_3D_SIZE 4294967296\n", 16384).is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use glam::{Mat4, Vec3};
+
+use crate::atmosphere::SkyPalette;
+use crate::gl_utils::{Texture, TextureFormat};
+use crate::hot_reload::ReloadableProgram;
+use crate::shaders::{PRECIPITATION_FRAGMENT_SHADER, PRECIPITATION_VERTEX_SHADER};
+use crate::weather::Weather;
+use crate::wind::ambient_wind;
+use crate::terrain::{height_at, SEA_LEVEL};
+use crate::world::{Building, GRID_SPACING};
+
+// Particles live in this box around the camera
+const BOX_SIZE: Vec3 = Vec3::new(80.0, 60.0, 80.0);
+// Particle counts at full intensity
+const RAIN_PARTICLES: f32 = 15000.0;
+const SNOW_PARTICLES: f32 = 6000.0;
+
+const RAIN_FALL_SPEED: f32 = 20.0;
+const SNOW_FALL_SPEED: f32 = 1.5;
+// How much of the wind each is carried by
+const RAIN_WIND: f32 = 0.5;
+const SNOW_WIND: f32 = 0.9;
+// Seconds of motion a rain streak covers
+const STREAK_TIME: f32 = 0.05;
+const RAIN_WIDTH: f32 = 0.04;
+const SNOW_SIZE: f32 = 0.12;
+
+// Cells around the camera whose floor heights are kept, enough to cover the
+// box, and texels per cell across, fine enough for podiums and twin towers
+const ROOF_GRID: i32 = 16;
+const ROOF_DETAIL: i32 = 4;
+
+// GPU rain and snow following the weather's precipitation
+pub struct Precipitation {
+    program: ReloadableProgram,
+    vao: u32,
+    roofs: Texture,
+    roof_origin: Option<(i32, i32)>,
+    rain_offset: Vec3,
+    snow_offset: Vec3,
+}
+
+impl Precipitation {
+    pub unsafe fn new() -> Self {
+        let mut vao = 0;
+        gl::GenVertexArrays(1, &mut vao);
+        Self {
+            program: ReloadableProgram::new("precipitation", PRECIPITATION_VERTEX_SHADER, PRECIPITATION_FRAGMENT_SHADER),
+            vao,
+            roofs: Texture::new(ROOF_GRID * ROOF_DETAIL, ROOF_GRID * ROOF_DETAIL, TextureFormat::RGBA16F),
+            roof_origin: None,
+            rain_offset: Vec3::ZERO,
+            snow_offset: Vec3::ZERO,
+        }
+    }
+
+    // Drift in the wind at the camera's height
+    fn rain_velocity(weather: &Weather, camera: Vec3) -> Vec3 {
+        Vec3::new(0.0, -RAIN_FALL_SPEED, 0.0) + ambient_wind(camera.y, &weather.current) * RAIN_WIND
+    }
+
+    fn snow_velocity(weather: &Weather, camera: Vec3) -> Vec3 {
+        Vec3::new(0.0, -SNOW_FALL_SPEED, 0.0) + ambient_wind(camera.y, &weather.current) * SNOW_WIND
+    }
+
+    pub fn update(&mut self, dt: f32, weather: &Weather, camera: Vec3) {
+        // Wrapped into the box in the shader, so only the fraction matters
+        self.rain_offset = (self.rain_offset + Self::rain_velocity(weather, camera) * dt) % BOX_SIZE;
+        self.snow_offset = (self.snow_offset + Self::snow_velocity(weather, camera) * dt) % BOX_SIZE;
+    }
+
+    // Refreshes the roof and ground heights when the camera moves to another
+    // cell
+    unsafe fn update_roofs(&mut self, camera: Vec3) {
+        let origin = (
+            (camera.x / GRID_SPACING).round() as i32 - ROOF_GRID / 2,
+            (camera.z / GRID_SPACING).round() as i32 - ROOF_GRID / 2,
+        );
+        if self.roof_origin == Some(origin) {
+            return;
+        }
+        self.roof_origin = Some(origin);
+        let size = ROOF_GRID * ROOF_DETAIL;
+        let texel = GRID_SPACING / ROOF_DETAIL as f32;
+        let mut pixels = vec![[0.0, 0.0, 0.0, 1.0]; (size * size) as usize];
+        for cz in 0..ROOF_GRID {
+            for cx in 0..ROOF_GRID {
+                let building = Building::at(origin.0 + cx, origin.1 + cz);
+                for j in 0..ROOF_DETAIL {
+                    for i in 0..ROOF_DETAIL {
+                        let (tx, tz) = (cx * ROOF_DETAIL + i, cz * ROOF_DETAIL + j);
+                        let x = (origin.0 as f32 - 0.5) * GRID_SPACING + (tx as f32 + 0.5) * texel;
+                        let z = (origin.1 as f32 - 0.5) * GRID_SPACING + (tz as f32 + 0.5) * texel;
+                        // The sea stops it just the same
+                        let ground = height_at(x, z).max(SEA_LEVEL);
+                        let roof = building.iter().flat_map(|b| &b.parts)
+                            .filter(|part| (part.bounds.min.x..part.bounds.max.x).contains(&x) && (part.bounds.min.z..part.bounds.max.z).contains(&z))
+                            .fold(ground, |top, part| top.max(part.bounds.max.y));
+                        pixels[(tz * size + tx) as usize] = [roof, 0.0, 0.0, 1.0];
+                    }
+                }
+            }
+        }
+        self.roofs.upload_rgba(&pixels);
+    }
+
+    // Draws into the bound HDR target after the opaque scene. `velocity` is
+    // the camera's, which stretches the rain streaks.
+    pub unsafe fn draw(&mut self, view_projection: &Mat4, camera: Vec3, velocity: Vec3, time: f32, weather: &Weather, palette: &SkyPalette) {
+        let intensity = weather.current.precipitation;
+        let snow = weather.current.snow;
+        let rain_count = (RAIN_PARTICLES * intensity * (1.0 - snow)) as i32;
+        let snow_count = (SNOW_PARTICLES * intensity * snow) as i32;
+        if rain_count == 0 && snow_count == 0 {
+            return;
+        }
+
+        self.program.poll();
+        self.update_roofs(camera);
+        let program = self.program.program();
+        program.bind();
+        self.roofs.bind(0);
+        program.set_i32("uRoofs", 0);
+        let origin = self.roof_origin.unwrap();
+        program.set_ivec2("uRoofOrigin", origin.0, origin.1);
+        program.set_f32("uGridSpacing", GRID_SPACING);
+        program.set_i32("uRoofDetail", ROOF_DETAIL);
+        program.set_f32("uSeaLevel", SEA_LEVEL);
+        program.set_mat4("uViewProjection", view_projection);
+        program.set_vec3("uCameraPos", camera);
+        program.set_vec3("uBoxSize", BOX_SIZE);
+        program.set_f32("uTime", time);
+        program.set_vec3("uLight", palette.ambient_light + (palette.sun_light + palette.moon_light) * 0.3);
+
+        gl::Enable(gl::BLEND);
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        gl::DepthMask(gl::FALSE);
+        gl::BindVertexArray(self.vao);
+
+        if rain_count > 0 {
+            program.set_i32("uSnow", 0);
+            program.set_vec3("uOffset", self.rain_offset);
+            program.set_vec3("uRelativeVelocity", Self::rain_velocity(weather, camera) - velocity);
+            program.set_f32("uStreakTime", STREAK_TIME);
+            program.set_f32("uSize", RAIN_WIDTH);
+            gl::DrawArrays(gl::TRIANGLES, 0, rain_count * 6);
+        }
+        if snow_count > 0 {
+            program.set_i32("uSnow", 1);
+            program.set_vec3("uOffset", self.snow_offset);
+            program.set_vec3("uRelativeVelocity", Self::snow_velocity(weather, camera) - velocity);
+            program.set_f32("uStreakTime", 0.0);
+            program.set_f32("uSize", SNOW_SIZE);
+            gl::DrawArrays(gl::TRIANGLES, 0, snow_count * 6);
+        }
+
+        gl::DepthMask(gl::TRUE);
+        gl::Disable(gl::BLEND);
+    }
+}
+
+impl Drop for Precipitation {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteVertexArrays(1, &self.vao) };
+    }
+}
@@ -0,0 +1,114 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::weather::WeatherKind;
+
+// Player-tweakable options, read from `settings.cfg` in the working
+// directory. One `key = value` per line, `#` at the start of a line or after
+// a space starts a comment, so paths can still contain one. Keys that are
+// missing or don't parse keep their defaults.
+pub const SETTINGS_FILE: &str = "settings.cfg";
+
+#[derive(Clone, Debug)]
+pub struct PostSettings {
+    pub fxaa: bool,
+    // 0 disables a pass; 1 is full strength
+    pub color_grading: f32,
+    pub vignette: f32,
+    pub motion_blur: f32,
+    // Adobe .cube file; the built-in grade is used when unset
+    pub lut: Option<PathBuf>,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self { fxaa: true, color_grading: 1.0, vignette: 0.35, motion_blur: 0.5, lut: None }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Settings {
+    pub post: PostSettings,
+    // Fixed weather; None lets it change by itself
+    pub weather: Option<WeatherKind>,
+    // Start in the unpowered glider
+    pub glider: bool,
+    // Which world to generate; None is terrain::DEFAULT_SEED
+    pub seed: Option<u16>,
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        Self::load_from(Path::new(SETTINGS_FILE))
+    }
+
+    pub fn load_from(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut settings = Self::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                eprintln!("{}:{}: expected `key = value`", SETTINGS_FILE, n + 1);
+                continue;
+            };
+            if let Err(e) = settings.set(key.trim(), value.trim()) {
+                eprintln!("{}:{}: {}", SETTINGS_FILE, n + 1, e);
+            }
+        }
+        settings
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let post = &mut self.post;
+        match key {
+            "post.fxaa" => post.fxaa = parse(value)?,
+            "post.color_grading" => post.color_grading = parse(value)?,
+            "post.vignette" => post.vignette = parse(value)?,
+            "post.motion_blur" => post.motion_blur = parse(value)?,
+            "post.lut" => post.lut = Some(PathBuf::from(value)),
+            "weather" => self.weather = if value == "auto" { None } else { Some(parse(value)?) },
+            "glider" => self.glider = parse(value)?,
+            "seed" => self.seed = Some(parse(value)?),
+            _ => return Err(format!("unknown setting `{}`", key)),
+        }
+        Ok(())
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut after_space = true;
+    for (i, c) in line.char_indices() {
+        if c == '#' && after_space {
+            return &line[..i];
+        }
+        after_space = c.is_whitespace();
+    }
+    line
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value `{}`", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_leave_paths_alone() {
+        let settings = Settings::parse("# grading\npost.lut = luts/#2.cube # warm\npost.vignette = 0.3#\nglider = true");
+        assert_eq!(settings.post.lut, Some(PathBuf::from("luts/#2.cube")));
+        assert!(settings.glider);
+        // Not a comment without a space before it, so not a number either
+        assert_eq!(settings.post.vignette, Settings::default().post.vignette);
+    }
+}
@@ -1,32 +1,221 @@
-pub const SKY_VERTEX_SHADER: &str = r#"
-    #version 330 core
-    layout (location = 0) in vec3 aPos;
+// Shared GLSL, pulled into the shaders below with `#include "<name>"`.
+// A module's position in SHADER_MODULES is its source string number in
+// driver logs (the including shader itself is 0).
+pub const SHADER_MODULES: &[(&str, &str)] = &[
+    ("atmosphere", ATMOSPHERE_GLSL),
+    ("color", COLOR_GLSL),
+    ("shadows", SHADOWS_GLSL),
+    ("clouds", CLOUDS_GLSL),
+    ("weather", WEATHER_GLSL),
+];
 
-    out vec3 WorldPos;
+// Single-scattering atmosphere (Rayleigh + Mie + ozone absorption) around a
+// spherical Earth. The sky pass integrates it along each view ray; the scene
+// pass uses the same model for aerial perspective on buildings. atmosphere.rs
+// mirrors these constants for CPU-side lighting.
+pub const ATMOSPHERE_GLSL: &str = r#"
+    const float PI = 3.14159265;
+    const float PLANET_RADIUS = 6360e3;
+    const float ATMOSPHERE_RADIUS = 6420e3;
+    const vec3 RAYLEIGH_SCATTERING = vec3(5.802e-6, 13.558e-6, 33.1e-6);
+    const float RAYLEIGH_HEIGHT = 8000.0;
+    const float MIE_SCATTERING = 3.996e-6;
+    const float MIE_EXTINCTION = 4.440e-6;
+    const float MIE_HEIGHT = 1200.0;
+    const float MIE_G = 0.8;
+    const vec3 OZONE_ABSORPTION = vec3(0.650e-6, 1.881e-6, 0.085e-6);
+    const float SUN_INTENSITY = 20.0;
+    // City-scale haze is much denser than the clean standard atmosphere
+    const float AERIAL_DENSITY_SCALE = 12.0;
+    // World y of sea level (see terrain.rs)
+    uniform float uSeaLevel;
 
-    uniform mat4 view;
-    uniform mat4 projection;
+    // Distances to the near/far hits of a sphere centred on the planet, or (-1, -1)
+    vec2 raySphere(vec3 ro, vec3 rd, float radius) {
+        float b = dot(ro, rd);
+        float c = dot(ro, ro) - radius * radius;
+        float d = b * b - c;
+        if (d < 0.0) return vec2(-1.0);
+        d = sqrt(d);
+        return vec2(-b - d, -b + d);
+    }
 
-    void main() {
-        WorldPos = aPos;
-        // Remove translation from view matrix for skybox (it stays with player)
-        mat4 viewRot = mat4(mat3(view)); 
-        vec4 pos = projection * viewRot * vec4(aPos, 1.0);
-        // Force depth to max (w) to render behind everything if we used depth test, 
-        // but we will just draw it first.
-        gl_Position = pos.xyww;
+    // Planet-centred position of a world-space point
+    vec3 atmospherePos(vec3 worldPos) {
+        return vec3(0.0, PLANET_RADIUS + max(worldPos.y - uSeaLevel, 1.0), 0.0);
+    }
+
+    // Relative densities at altitude h: (rayleigh, mie, ozone)
+    vec3 atmosphereDensity(float h) {
+        float ozone = max(0.0, 1.0 - abs(h - 25000.0) / 15000.0);
+        return vec3(exp(-h / RAYLEIGH_HEIGHT), exp(-h / MIE_HEIGHT), ozone);
+    }
+
+    vec3 extinction(vec3 density) {
+        return RAYLEIGH_SCATTERING * density.x + MIE_EXTINCTION * density.y + OZONE_ABSORPTION * density.z;
+    }
+
+    // Fraction of sunlight reaching p. Zero once the planet blocks the sun,
+    // which is what casts the Earth shadow after sunset.
+    vec3 sunTransmittance(vec3 p, vec3 sunDir) {
+        vec2 ground = raySphere(p, sunDir, PLANET_RADIUS);
+        if (ground.x > 0.0) return vec3(0.0);
+
+        float len = raySphere(p, sunDir, ATMOSPHERE_RADIUS).y;
+        const int STEPS = 6;
+        float ds = len / float(STEPS);
+        vec3 depth = vec3(0.0);
+        for (int i = 0; i < STEPS; i++) {
+            vec3 s = p + sunDir * ((float(i) + 0.5) * ds);
+            depth += atmosphereDensity(length(s) - PLANET_RADIUS) * ds;
+        }
+        return exp(-extinction(depth));
+    }
+
+    float rayleighPhase(float mu) {
+        return 3.0 / (16.0 * PI) * (1.0 + mu * mu);
+    }
+
+    // Cornette-Shanks
+    float miePhase(float mu) {
+        float g2 = MIE_G * MIE_G;
+        float k = 3.0 / (8.0 * PI) * (1.0 - g2) / (2.0 + g2);
+        return k * (1.0 + mu * mu) / pow(1.0 + g2 - 2.0 * MIE_G * mu, 1.5);
+    }
+
+    // In-scattered sunlight along ro + rd * [0, len]. Also returns the
+    // transmittance of the segment. `sunT` < 0 samples the sun per step,
+    // otherwise it's used as-is (cheap path for short city-scale rays).
+    vec3 integrateScattering(vec3 ro, vec3 rd, float len, vec3 sunDir, int steps,
+                             float densityScale, vec3 sunT, out vec3 transmittance) {
+        float mu = dot(rd, sunDir);
+        float phaseR = rayleighPhase(mu);
+        float phaseM = miePhase(mu);
+        float ds = len / float(steps);
+
+        vec3 optical = vec3(0.0);
+        vec3 radiance = vec3(0.0);
+        for (int i = 0; i < steps; i++) {
+            vec3 p = ro + rd * ((float(i) + 0.5) * ds);
+            vec3 density = atmosphereDensity(length(p) - PLANET_RADIUS) * densityScale;
+            optical += density * ds;
+
+            vec3 lightT = sunT.x < 0.0 ? sunTransmittance(p, sunDir) : sunT;
+            vec3 scattering = RAYLEIGH_SCATTERING * density.x * phaseR + MIE_SCATTERING * density.y * phaseM;
+            radiance += exp(-extinction(optical)) * lightT * scattering * ds;
+        }
+        transmittance = exp(-extinction(optical));
+        return radiance * SUN_INTENSITY;
+    }
+
+    // Radiance of the sky seen from cameraPos in direction dir
+    vec3 skyRadiance(vec3 cameraPos, vec3 dir, vec3 sunDir) {
+        vec3 ro = atmospherePos(cameraPos);
+        vec2 ground = raySphere(ro, dir, PLANET_RADIUS);
+        float len = ground.x > 0.0 ? ground.x : raySphere(ro, dir, ATMOSPHERE_RADIUS).y;
+        vec3 transmittance;
+        return integrateScattering(ro, dir, len, sunDir, 16, 1.0, vec3(-1.0), transmittance);
+    }
+
+    // Faint night-sky glow so the sky and haze don't go pitch black after dusk
+    vec3 airglow(vec3 dir) {
+        return mix(vec3(0.0, 0.0, 0.002), vec3(0.001, 0.002, 0.008), pow(1.0 - max(dir.y, 0.0), 2.5));
+    }
+
+    // Aerial perspective: dims what's behind a city-scale ray and adds the
+    // light scattered into it. Sunlight is assumed constant along the ray.
+    vec3 applyHaze(vec3 color, vec3 worldPos, vec3 cameraPos, vec3 sunDir) {
+        vec3 ro = atmospherePos(cameraPos);
+        vec3 delta = worldPos - cameraPos;
+        float len = length(delta);
+        vec3 rd = delta / max(len, 1e-3);
+        vec3 sunT = sunTransmittance(ro, sunDir);
+
+        vec3 transmittance;
+        vec3 inscatter = integrateScattering(ro, rd, len, sunDir, 6, AERIAL_DENSITY_SCALE, sunT, transmittance);
+        return color * transmittance + inscatter + airglow(rd) * (1.0 - transmittance);
     }
 "#;
 
-pub const SKY_FRAGMENT_SHADER: &str = r#"
-    #version 330 core
-    in vec3 WorldPos;
-    out vec4 FragColor;
+// Colour space helpers. Everything up to the tonemap pass works in linear
+// HDR; colours authored by eye (building palettes, glass) are sRGB.
+pub const COLOR_GLSL: &str = r#"
+    vec3 srgbToLinear(vec3 c) {
+        return mix(c / 12.92, pow((c + 0.055) / 1.055, vec3(2.4)), step(0.04045, c));
+    }
 
-    uniform vec3 uSunDir;
-    uniform vec3 uMoonDir; // Explicit moon direction
-    uniform float uTime;
-    uniform vec3 uCameraPos; // Added camera position for world-space clouds
+    vec3 linearToSrgb(vec3 c) {
+        return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+    }
+
+    float luminance(vec3 c) {
+        return dot(c, vec3(0.2126, 0.7152, 0.0722));
+    }
+"#;
+
+// Shadow lookups for the scene pass. The sun uses SUN_CASCADES cascades
+// packed side by side in one atlas; the moon gets a single map. Matrices map
+// world space to [0, 1] tile coordinates and depth (see shadows.rs).
+pub const SHADOWS_GLSL: &str = r#"
+    const int SUN_CASCADES = 3;
+
+    uniform sampler2DShadow uSunShadowMap;
+    uniform mat4 uSunShadowMatrices[SUN_CASCADES];
+    // World size of a shadow texel in each cascade, for the normal offset
+    uniform float uSunShadowTexels[SUN_CASCADES];
+    uniform sampler2DShadow uMoonShadowMap;
+    uniform mat4 uMoonShadowMatrix;
+    uniform float uMoonShadowTexel;
+
+    // Four bilinear comparisons, so effectively a 3x3 tent filter
+    float shadowPcf(sampler2DShadow map, vec2 uv, float depth, vec2 texel) {
+        float lit = texture(map, vec3(uv + vec2(-0.5, -0.5) * texel, depth));
+        lit += texture(map, vec3(uv + vec2(0.5, -0.5) * texel, depth));
+        lit += texture(map, vec3(uv + vec2(-0.5, 0.5) * texel, depth));
+        lit += texture(map, vec3(uv + vec2(0.5, 0.5) * texel, depth));
+        return lit * 0.25;
+    }
+
+    // 1 when lit. Pushed off the surface along the normal against acne.
+    // Beyond the last cascade everything counts as lit, fading in at its edge.
+    float sunShadow(vec3 worldPos, vec3 normal) {
+        vec2 atlasTexel = 1.0 / vec2(textureSize(uSunShadowMap, 0));
+        float margin = 2.0 * atlasTexel.y;
+        for (int i = 0; i < SUN_CASCADES; i++) {
+            vec3 p = worldPos + normal * uSunShadowTexels[i] * 1.5;
+            vec3 coord = (uSunShadowMatrices[i] * vec4(p, 1.0)).xyz;
+            if (all(greaterThan(coord.xy, vec2(margin))) && all(lessThan(coord.xy, vec2(1.0 - margin))) && coord.z < 1.0) {
+                vec2 uv = vec2((coord.x + float(i)) / float(SUN_CASCADES), coord.y);
+                float lit = shadowPcf(uSunShadowMap, uv, coord.z, atlasTexel);
+                if (i == SUN_CASCADES - 1) {
+                    vec2 edge = abs(coord.xy - 0.5) * 2.0;
+                    lit = mix(lit, 1.0, smoothstep(0.8, 1.0, max(edge.x, edge.y)));
+                }
+                return lit;
+            }
+        }
+        return 1.0;
+    }
+
+    float moonShadow(vec3 worldPos, vec3 normal) {
+        vec2 texel = 1.0 / vec2(textureSize(uMoonShadowMap, 0));
+        vec3 coord = (uMoonShadowMatrix * vec4(worldPos + normal * uMoonShadowTexel * 1.5, 1.0)).xyz;
+        if (any(lessThan(coord.xy, vec2(0.0))) || any(greaterThan(coord.xy, vec2(1.0))) || coord.z >= 1.0) {
+            return 1.0;
+        }
+        return shadowPcf(uMoonShadowMap, coord.xy, coord.z, texel);
+    }
+"#;
+
+// Cloud layer density, shared by the sky (which raymarches it) and the scene
+// (which casts its shadows). CloudLayer in clouds.rs sets the uniforms.
+pub const CLOUDS_GLSL: &str = r#"
+    uniform float uCloudBottom;
+    uniform float uCloudTop;
+    // 0 is a clear sky, 1 solid overcast
+    uniform float uCloudCoverage;
+    // How far the wind has carried the clouds
+    uniform vec3 uCloudOffset;
 
     // --- Noise Functions ---
     float hash(vec3 p) {
@@ -64,20 +253,17 @@ pub const SKY_FRAGMENT_SHADER: &str = r#"
 
     // Cloud density function
     float mapClouds(vec3 p) {
-        // Cloud layer bounds
-        float cloudBottom = 150.0;
-        float cloudTop = 250.0;
         float height = p.y;
         
         // Basic height bounds check
-        if (height < cloudBottom || height > cloudTop) return 0.0;
+        if (height < uCloudBottom || height > uCloudTop) return 0.0;
         
         // Height gradient (soft edges top/bottom)
-        float hNorm = (height - cloudBottom) / (cloudTop - cloudBottom);
+        float hNorm = (height - uCloudBottom) / (uCloudTop - uCloudBottom);
         float hDensity = 1.0 - pow(abs(hNorm - 0.5) * 2.0, 2.0); // Parabola: 0 at edges, 1 in middle
         
         // Wind scrolling
-        vec3 q = p - vec3(uTime * 10.0, 0.0, uTime * 4.0);
+        vec3 q = p - uCloudOffset;
         
         // Main shape
         float base = fbm(q * 0.015);
@@ -88,12 +274,87 @@ pub const SKY_FRAGMENT_SHADER: &str = r#"
         // Combine
         float f = base - detail * 0.3;
         
-        // Threshold and Density
-        float density = smoothstep(0.4, 0.7, f) * hDensity;
+        // Threshold and Density; more coverage lowers the threshold
+        float edge = mix(0.7, -0.05, uCloudCoverage);
+        float density = smoothstep(edge, edge + 0.3, f) * hDensity;
         
         return clamp(density, 0.0, 1.0);
     }
-    
+
+    // Fraction of sunlight getting through the cloud layer above worldPos
+    float cloudShadow(vec3 worldPos, vec3 sunDir) {
+        // Flatten very low suns so the lookups stay near the city
+        vec3 dir = normalize(vec3(sunDir.x, max(sunDir.y, 0.1), sunDir.z));
+        float density = 0.0;
+        for (int i = 0; i < 3; i++) {
+            float h = mix(uCloudBottom, uCloudTop, (float(i) + 0.5) / 3.0);
+            density += mapClouds(worldPos + dir * ((h - worldPos.y) / dir.y));
+        }
+        // Some light always scatters through
+        return mix(0.25, 1.0, exp(-density * 1.5));
+    }
+"#;
+
+// Low-lying fog from the weather (see weather.rs). Its density falls off
+// exponentially with height, so towers can rise out of a fog bank.
+pub const WEATHER_GLSL: &str = r#"
+    // Fog extinction per unit of distance at y = 0, and its scale height
+    uniform float uFogDensity;
+    uniform float uFogHeight;
+    // Colour of fog and cloud interiors (see atmosphere.rs)
+    uniform vec3 uFogColor;
+
+    // Optical depth of the fog between the camera and worldPos
+    float fogDepth(vec3 worldPos, vec3 cameraPos) {
+        float dy = (worldPos.y - cameraPos.y) / uFogHeight;
+        // Average density along the ray, relative to the camera's
+        float falloff = abs(dy) > 1e-3 ? (1.0 - exp(-dy)) / dy : 1.0;
+        return uFogDensity * exp(-cameraPos.y / uFogHeight) * falloff * distance(worldPos, cameraPos);
+    }
+"#;
+
+pub const SKY_VERTEX_SHADER: &str = r#"
+    #version 330 core
+    layout (location = 0) in vec3 aPos;
+
+    out vec3 WorldPos;
+
+    uniform mat4 view;
+    uniform mat4 projection;
+
+    void main() {
+        WorldPos = aPos;
+        // Remove translation from view matrix for skybox (it stays with player)
+        mat4 viewRot = mat4(mat3(view)); 
+        vec4 pos = projection * viewRot * vec4(aPos, 1.0);
+        // Force depth to max (w) to render behind everything if we used depth test, 
+        // but we will just draw it first.
+        gl_Position = pos.xyww;
+    }
+"#;
+
+pub const SKY_FRAGMENT_SHADER: &str = r#"
+    #version 330 core
+    in vec3 WorldPos;
+    out vec4 FragColor;
+
+    uniform vec3 uSunDir;
+    uniform vec3 uMoonDir; // Explicit moon direction
+    uniform float uTime;
+    uniform vec3 uCameraPos; // Added camera position for world-space clouds
+
+    // Sky-only palette terms (see atmosphere.rs)
+    uniform float uStarOpacity;
+    uniform vec3 uCloudSunLight;
+    uniform vec3 uCloudAmbient;
+
+    #include "atmosphere"
+    #include "clouds"
+    #include "weather"
+
+    // The sky sits behind the farthest buildings, this far away
+    uniform float uDrawDistance;
+
     // Henyey-Greenstein phase function
     float hg(float a, float g) {
         float g2 = g*g;
@@ -102,69 +363,39 @@ pub const SKY_FRAGMENT_SHADER: &str = r#"
 
     void main() {
         vec3 viewDir = normalize(WorldPos);
-        float sunHeight = uSunDir.y;
-
-        // --- Sky Gradient ---
-        vec3 dayZenith = vec3(0.1, 0.4, 0.85);
-        vec3 dayHorizon = vec3(0.6, 0.8, 0.95);
-        vec3 sunsetZenith = vec3(0.15, 0.1, 0.35);
-        vec3 sunsetHorizon = vec3(0.9, 0.45, 0.1);
-        vec3 nightZenith = vec3(0.0, 0.0, 0.02);
-        vec3 nightHorizon = vec3(0.01, 0.02, 0.08);
-
-        vec3 zenithColor, horizonColor;
-        vec3 sunColor = vec3(1.0, 1.0, 0.95);
-        float sunIntensity = 1.0;
-        float starOpacity = 0.0;
-        float cloudBrightness = 1.0;
-
-        if (sunHeight > 0.15) {
-            zenithColor = dayZenith;
-            horizonColor = dayHorizon;
-            starOpacity = 0.0;
-            cloudBrightness = 1.2;
-        } else if (sunHeight > -0.15) {
-            float t = (sunHeight + 0.15) / 0.30;
-            zenithColor = mix(sunsetZenith, dayZenith, t);
-            horizonColor = mix(sunsetHorizon, dayHorizon, t);
-            sunColor = vec3(1.0, 0.8, 0.6); 
-            starOpacity = mix(1.0, 0.0, t);
-            cloudBrightness = mix(0.9, 1.2, t); 
-        } else {
-            zenithColor = nightZenith;
-            horizonColor = nightHorizon;
-            sunIntensity = 0.0;
-            starOpacity = 1.0;
-            cloudBrightness = 0.15;
-        }
-
-        float horizonMix = pow(1.0 - max(viewDir.y, 0.0), 2.5);
-        vec3 skyColor = mix(zenithColor, horizonColor, horizonMix);
+        // --- Atmosphere ---
+        vec3 skyColor = skyRadiance(uCameraPos, viewDir, uSunDir) + airglow(viewDir);
+        vec3 backgroundSky = skyColor;
 
         // --- Stars ---
-        if (starOpacity > 0.0) {
+        if (uStarOpacity > 0.0) {
             vec2 starPos = viewDir.xz / (viewDir.y + 1.5) * 200.0; 
             float n = hash(floor(vec3(starPos.x * 1.5, starPos.y * 1.5, 0.0))); 
             float star = step(0.998, n); 
-            skyColor += vec3(star * starOpacity * 0.8);
+            skyColor += vec3(star * uStarOpacity * 0.3);
         }
 
         // --- Sun & Moon ---
-        if (sunIntensity > 0.0) {
-            float sunDot = dot(viewDir, uSunDir);
-            float sunDisk = smoothstep(0.9985, 0.999, sunDot);
+        float sunDot = dot(viewDir, uSunDir);
+        if (sunDot > 0.9) {
+            // Reddened by the air it shines through; gone below the horizon
+            vec3 sunColor = sunTransmittance(atmospherePos(uCameraPos), viewDir);
+            // Far brighter than anything else on screen, so it blooms
+            float sunDisk = smoothstep(0.9985, 0.999, sunDot) * 40.0;
+            // Enough leaks through the cloud march to bloom; hide it under overcast
+            sunDisk *= 1.0 - smoothstep(0.5, 1.0, uCloudCoverage);
             float sunGlow = pow(max(sunDot, 0.0), 400.0) * 0.4;
-            skyColor += (sunDisk + sunGlow) * sunColor * sunIntensity;
+            skyColor += (sunDisk + sunGlow) * sunColor;
         }
         // Use explicit moon direction
         float moonDot = dot(viewDir, uMoonDir);
         float moonDisk = smoothstep(0.997, 0.998, moonDot);
         float moonGlow = pow(max(moonDot, 0.0), 200.0) * 0.15;
-        skyColor += (moonDisk + moonGlow) * vec3(0.9, 0.95, 1.0) * max(starOpacity, 0.2);
+        skyColor += (moonDisk * 0.6 + moonGlow * 0.1) * vec3(0.9, 0.95, 1.0) * max(uStarOpacity, 0.2);
 
         // --- Volumetric Clouds (World Space) ---
-        float cloudBottom = 150.0;
-        float cloudTop = 250.0;
+        float cloudBottom = uCloudBottom;
+        float cloudTop = uCloudTop;
         float camY = uCameraPos.y;
         
         float tMin = -1.0;
@@ -231,20 +462,19 @@ pub const SKY_FRAGMENT_SHADER: &str = r#"
                         float powder = 1.0 - exp(-den * 2.0);
                         
                         // Lighting components
-                        // Force white sun for clouds to avoid brown
-                        vec3 cloudSunColor = vec3(1.0, 0.98, 0.95); 
+                        vec3 cloudSunColor = uCloudSunLight;
                         
                         // "Silver Lining" Boost: If looking at sun, ignore some shadow
                         float sunGlow = smoothstep(0.8, 1.0, cosTheta);
                         float effectiveTransmittance = mix(lightTransmittance, 1.0, sunGlow * 0.8);
 
-                        vec3 directLight = cloudSunColor * effectiveTransmittance * phase * powder * 3.0;
+                        vec3 directLight = cloudSunColor * effectiveTransmittance * phase * powder;
                         
-                        // Ambient: Pure bright white with slight blue tint
-                        vec3 ambientLight = vec3(0.9, 0.95, 1.0) * 1.2;
+                        // Ambient: skylight from above
+                        vec3 ambientLight = uCloudAmbient;
                         
                         // Final scatter color for this sample
-                        vec3 scatColor = (directLight + ambientLight) * cloudBrightness;
+                        vec3 scatColor = directLight + ambientLight;
                         
                         // Accumulate
                         float alpha = den * 0.4;
@@ -257,15 +487,21 @@ pub const SKY_FRAGMENT_SHADER: &str = r#"
                 }
                 
                 // Blend clouds into sky
-                skyColor = mix(skyColor, cloudColorAcc, totalDensity);
+                // The last step can overshoot 1, which would extrapolate
+                skyColor = mix(skyColor, cloudColorAcc, min(totalDensity, 1.0));
                 
                 // Fog blend (atmospheric perspective)
                 float cloudDist = tMin;
                 float fogAmt = 1.0 - exp(-cloudDist * 0.002);
-                skyColor = mix(skyColor, horizonColor, fogAmt * 0.8);
+                skyColor = mix(skyColor, backgroundSky, fogAmt * 0.8);
             }
         }
 
+        if (uFogDensity > 0.0) {
+            float fog = 1.0 - exp(-fogDepth(uCameraPos + viewDir * uDrawDistance, uCameraPos));
+            skyColor = mix(skyColor, uFogColor, fog);
+        }
+
         FragColor = vec4(skyColor, 1.0);
     }
 "#;
@@ -273,20 +509,45 @@ pub const SKY_FRAGMENT_SHADER: &str = r#"
 pub const SCENE_VERTEX_SHADER: &str = r#"
     #version 330 core
     layout (location = 0) in vec3 aPos;
+    // Per-instance attributes (see instancing.rs)
+    layout (location = 1) in mat4 aModel;
+    layout (location = 5) in vec3 aColor;
+    layout (location = 9) in float aFacade;
+    // Mesh attributes (see mesh.rs)
+    layout (location = 6) in vec3 aNormal;
+    layout (location = 7) in vec2 aUV;
+    layout (location = 8) in vec4 aTangent;
 
     out vec3 WorldPos;
     out float HeightRatio;
+    out vec3 BaseColor;
+    out vec3 Normal;
+    // Position on the face in world units, from its corner
+    out vec2 FacadeUV;
+    // Varies the lit windows between buildings and faces
+    flat out vec2 FacadeSeed;
+    flat out int Facade;
 
-    uniform mat4 model;
     uniform mat4 view;
     uniform mat4 projection;
     uniform float uMaxHeight;
 
     void main() {
-        vec4 worldPosition = model * vec4(aPos, 1.0);
+        vec4 worldPosition = aModel * vec4(aPos, 1.0);
         WorldPos = worldPosition.xyz;
-        // Normalized height for gradient
-        HeightRatio = clamp((worldPosition.y + 10.0) / uMaxHeight, 0.0, 1.0);
+        BaseColor = aColor;
+
+        mat3 model = mat3(aModel);
+        Normal = normalize(transpose(inverse(model)) * aNormal);
+        // Mesh UVs are in mesh units; scale them by the instance's stretch
+        // along the tangent frame
+        vec3 bitangent = cross(aNormal, aTangent.xyz) * aTangent.w;
+        FacadeUV = aUV * vec2(length(model * aTangent.xyz), length(model * bitangent));
+        FacadeSeed = aModel[3].xz + aNormal.xz * 17.0;
+        Facade = int(aFacade + 0.5);
+        // Height up the part for the gradient, from its foot rather than
+        // from sea level, so buildings on hills aren't paler for it
+        HeightRatio = clamp((aPos.y + 0.5) * length(aModel[1].xyz) / uMaxHeight, 0.0, 1.0);
         gl_Position = projection * view * worldPosition;
     }
 "#;
@@ -295,132 +556,658 @@ pub const SCENE_FRAGMENT_SHADER: &str = r#"
     #version 330 core
     in vec3 WorldPos;
     in float HeightRatio;
+    in vec3 BaseColor;
+    in vec3 Normal;
+    in vec2 FacadeUV;
+    flat in vec2 FacadeSeed;
+    flat in int Facade;
     out vec4 FragColor;
 
-    uniform vec3 uBaseColor;
     uniform vec3 uSunDir;
     uniform vec3 uMoonDir;
     uniform vec3 uCameraPos;
 
+    // Scene lighting terms (see atmosphere.rs)
+    uniform vec3 uSunLightColor;
+    uniform vec3 uMoonLightColor;
+    uniform vec3 uAmbientLight;
+    uniform float uNightFactor;
+    // Fog extinction when the camera is inside a cloud, 0 in clear air
+    uniform float uCloudFog;
+    // How soaked the city is after rain, 0..1
+    uniform float uWetness;
+
+    #include "atmosphere"
+    #include "color"
+    #include "shadows"
+    #include "clouds"
+    #include "weather"
+
     // Random function for window varying
     float random(vec2 st) {
         return fract(sin(dot(st.xy, vec2(12.9898,78.233))) * 43758.5453123);
     }
 
+    // Window grid of each facade style (see archetypes::Facade): cells per
+    // world unit across and up, and the window's corners within a cell
+    void facadeGrid(int facade, out vec2 cells, out vec4 window) {
+        if (facade == 2) {        // Curtain wall: glass between thin mullions
+            cells = vec2(0.8, 1.2); window = vec4(0.06, 0.1, 1.0, 1.0);
+        } else if (facade == 3) { // Residential: small, widely spaced
+            cells = vec2(0.7, 1.0); window = vec4(0.3, 0.35, 0.7, 0.8);
+        } else if (facade == 4) { // Industrial: a ribbon high in each storey
+            cells = vec2(1.0, 0.25); window = vec4(0.0, 0.7, 1.0, 0.85);
+        } else if (facade == 5) { // Loft: tall warehouse windows
+            cells = vec2(0.6, 0.6); window = vec4(0.2, 0.15, 0.8, 0.85);
+        } else {                  // Office
+            cells = vec2(1.5); window = vec4(0.3, 0.3, 1.0, 1.0);
+        }
+    }
+
+    // The ground (see terrain.rs): the instance's grass, rock on steep
+    // slopes and sand along the shore
+    vec3 terrainColor(vec3 grass, vec3 normal) {
+        vec3 rock = srgbToLinear(vec3(0.45, 0.42, 0.38));
+        vec3 sand = srgbToLinear(vec3(0.76, 0.70, 0.52));
+        vec3 color = mix(grass, rock, smoothstep(0.85, 0.7, normal.y));
+        return mix(sand, color, smoothstep(uSeaLevel + 1.0, uSeaLevel + 3.0, WorldPos.y));
+    }
+
     void main() {
-        // --- Calculate Face Normal ---
-        vec3 xTangent = dFdx(WorldPos);
-        vec3 yTangent = dFdy(WorldPos);
-        vec3 normal = normalize(cross(xTangent, yTangent));
+        vec3 normal = normalize(Normal);
 
-        // --- Time/Lighting Factors ---
         float sunHeight = uSunDir.y;
-        vec3 sunLightColor = vec3(0.0);
-        vec3 moonLightColor = vec3(0.0);
-        vec3 ambientLight = vec3(0.0);
-        float nightFactor = 0.0;
-
-        if (sunHeight > 0.15) {
-             // Day
-             sunLightColor = vec3(1.0, 0.95, 0.9);
-             ambientLight = vec3(0.6, 0.6, 0.65);
-             nightFactor = 0.0;
-        } else if (sunHeight > -0.15) {
-             // Sunset / Sunrise
-             sunLightColor = vec3(1.0, 0.6, 0.3);
-             ambientLight = vec3(0.4, 0.3, 0.4);
-             float t = (sunHeight + 0.15) / 0.30;
-             sunLightColor *= t; 
-             nightFactor = 1.0 - t;
-        } else {
-             // Night
-             ambientLight = vec3(0.02, 0.02, 0.05);
-             nightFactor = 1.0;
-        }
-        
-        // Moon
-        if (uMoonDir.y > 0.0) {
-            float moonFactor = clamp(uMoonDir.y * 2.0, 0.0, 1.0);
-            moonLightColor = vec3(0.2, 0.3, 0.5) * moonFactor;
-        }
 
         // --- Materials ---
-        vec3 baseColor = uBaseColor;
-        vec3 topColor = vec3(0.95);
-        vec3 wallColor = mix(baseColor, topColor, HeightRatio * 0.8);
+        vec3 baseColor = srgbToLinear(BaseColor);
+        vec3 topColor = srgbToLinear(vec3(0.95));
+        vec3 wallColor = Facade == 6 ? terrainColor(baseColor, normal) : mix(baseColor, topColor, HeightRatio * 0.25);
         
         // Window Logic
         bool isWindow = false;
-        if (abs(normal.y) < 0.5) { // Vertical walls
-            vec2 gridPos;
-            if (abs(normal.x) > 0.5) { gridPos = WorldPos.zy; } 
-            else { gridPos = WorldPos.xy; }
-            
-            vec2 st = gridPos * 1.5; 
-            vec2 ipos = floor(st);
-            vec2 fpos = fract(st);
+        vec2 cells;
+        vec4 window;
+        facadeGrid(Facade, cells, window);
+        if (abs(normal.y) < 0.5 && Facade != 0 && Facade != 6) { // Vertical walls
+            vec2 fpos = fract(FacadeUV * cells);
             // Window mask
-            float w = step(0.3, fpos.x) * step(0.3, fpos.y);
-            if (w > 0.5) { isWindow = true; }
+            isWindow = all(greaterThanEqual(fpos, window.xy)) && all(lessThan(fpos, window.zw));
             
             // Apply window material (Dark glass)
             if (isWindow) {
-                wallColor = vec3(0.1, 0.15, 0.2); // Dark blueish grey glass
+                wallColor = srgbToLinear(vec3(0.1, 0.15, 0.2)); // Dark blueish grey glass
             }
         }
 
+        // Rain darkens the concrete and leaves a glossy film, thickest on
+        // roofs and streets where it pools
+        float wet = uWetness * (normal.y > 0.5 ? 1.0 : 0.6);
+        if (!isWindow) { wallColor *= 1.0 - 0.5 * wet; }
+
         // --- Apply Lighting ---
+        // Faces turned away from a light are dark anyway; skip their lookups
         float sunDiff = max(dot(normal, uSunDir), 0.0);
+        float sunVisibility = sunDiff > 0.0 ? sunShadow(WorldPos, normal) * cloudShadow(WorldPos, uSunDir) : 0.0;
+        sunDiff *= sunVisibility;
         float moonDiff = max(dot(normal, uMoonDir), 0.0);
+        if (moonDiff > 0.0) { moonDiff *= moonShadow(WorldPos, normal); }
 
         // Add specular for glass during day?
+        vec3 viewDir = normalize(uCameraPos - WorldPos);
         float spec = 0.0;
         if (isWindow && sunHeight > 0.0) {
-            vec3 viewDir = normalize(uCameraPos - WorldPos);
             vec3 reflectDir = reflect(-uSunDir, normal);
-            spec = pow(max(dot(viewDir, reflectDir), 0.0), 32.0) * 0.8;
+            spec = pow(max(dot(viewDir, reflectDir), 0.0), 32.0) * 0.8 * sunVisibility;
+        }
+        // Wet surfaces glint in the sun and mirror the sky at grazing angles
+        vec3 wetSheen = vec3(0.0);
+        if (wet > 0.0) {
+            float glint = pow(max(dot(viewDir, reflect(-uSunDir, normal)), 0.0), 64.0) * sunVisibility;
+            float moonGlint = pow(max(dot(viewDir, reflect(-uMoonDir, normal)), 0.0), 64.0);
+            float fresnel = 0.04 + 0.96 * pow(1.0 - max(dot(viewDir, normal), 0.0), 5.0);
+            wetSheen = wet * (glint * uSunLightColor + moonGlint * uMoonLightColor + fresnel * uAmbientLight);
         }
 
-        vec3 lighting = wallColor * ambientLight 
-                      + wallColor * sunLightColor * sunDiff 
-                      + wallColor * moonLightColor * moonDiff
-                      + vec3(1.0) * spec * sunLightColor; // Specular highlight
+        vec3 lighting = wallColor * uAmbientLight 
+                      + wallColor * uSunLightColor * sunDiff 
+                      + wallColor * uMoonLightColor * moonDiff
+                      + vec3(1.0) * spec * uSunLightColor // Specular highlight
+                      + wetSheen;
 
         // --- Window Emission (Night) ---
-        if (isWindow && nightFactor > 0.0) {
-            vec2 gridPos = (abs(normal.x) > 0.5) ? WorldPos.zy : WorldPos.xy;
-            vec2 ipos = floor(gridPos * 1.5);
-            float r = random(ipos);
+        if (isWindow && uNightFactor > 0.0) {
+            vec2 ipos = floor(FacadeUV * cells);
+            float r = random(ipos + FacadeSeed);
             float lit = step(0.4, r); // 60% lit
             
             vec3 emitColor = vec3(1.0, 0.85, 0.5); // Warm light
-            lighting += emitColor * lit * nightFactor * 1.5;
+            lighting += emitColor * lit * uNightFactor * 2.0;
         }
 
-        // Fog
-        // Increased density to hide world borders
-        float dist = length(WorldPos - uCameraPos);
-        
-        vec3 dayZenith = vec3(0.1, 0.4, 0.85);
-        vec3 dayHorizon = vec3(0.6, 0.8, 0.95);
-        vec3 sunsetZenith = vec3(0.15, 0.1, 0.35);
-        vec3 sunsetHorizon = vec3(0.9, 0.45, 0.1);
-        vec3 nightZenith = vec3(0.0, 0.0, 0.02);
-        vec3 nightHorizon = vec3(0.01, 0.02, 0.08);
-
-        vec3 horizonColor;
-        if (sunHeight > 0.15) { horizonColor = dayHorizon; }
-        else if (sunHeight > -0.15) { 
-             float t = (sunHeight + 0.15) / 0.30;
-             horizonColor = mix(sunsetHorizon, dayHorizon, t); 
+        vec3 finalColor = applyHaze(lighting, WorldPos, uCameraPos, uSunDir);
+        float fog = fogDepth(WorldPos, uCameraPos) + distance(WorldPos, uCameraPos) * uCloudFog;
+        finalColor = mix(finalColor, uFogColor, 1.0 - exp(-fog));
+
+        FragColor = vec4(finalColor, 1.0);
+    }
+"#;
+
+// Depth-only pass for shadow maps, instanced like the scene
+pub const SHADOW_VERTEX_SHADER: &str = r#"
+    #version 330 core
+    layout (location = 0) in vec3 aPos;
+    layout (location = 1) in mat4 aModel;
+
+    uniform mat4 uLightViewProjection;
+
+    void main() {
+        gl_Position = uLightViewProjection * aModel * vec4(aPos, 1.0);
+    }
+"#;
+
+pub const SHADOW_FRAGMENT_SHADER: &str = r#"
+    #version 330 core
+
+    void main() {
+    }
+"#;
+
+// Rain and snow, generated from gl_VertexID (6 vertices per particle). Each
+// particle has a fixed random spot in a box that travels with the camera and
+// wraps around it as the particles fall and drift (see precipitation.rs).
+pub const PRECIPITATION_VERTEX_SHADER: &str = r#"
+    #version 330 core
+    out vec2 Coord;
+    out float Fade;
+
+    uniform mat4 uViewProjection;
+    uniform vec3 uCameraPos;
+    uniform vec3 uBoxSize;
+    // How far the wind and gravity have carried the particles
+    uniform vec3 uOffset;
+    // Particle velocity relative to the camera, and how many seconds of it
+    // a rain streak covers
+    uniform vec3 uRelativeVelocity;
+    uniform float uStreakTime;
+    uniform float uSize;
+    uniform int uSnow;
+    uniform float uTime;
+
+    // Height of the highest roof or the ground (r) across the grid cells
+    // around the camera, uRoofDetail texels to a cell, origin at the cell
+    // uRoofOrigin. Beyond them everything falls to the sea.
+    uniform sampler2D uRoofs;
+    uniform ivec2 uRoofOrigin;
+    uniform int uRoofDetail;
+    uniform float uGridSpacing;
+    uniform float uSeaLevel;
+
+    const vec2 CORNERS[6] = vec2[](
+        vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+        vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+    );
+
+    float random(float n) {
+        return fract(sin(n) * 43758.5453);
+    }
+
+    // Top of whatever stands under p: a roof, or the ground
+    float floorHeight(vec3 p) {
+        vec2 cells = p.xz / uGridSpacing - vec2(uRoofOrigin) + 0.5;
+        ivec2 texel = ivec2(floor(cells * float(uRoofDetail)));
+        ivec2 size = textureSize(uRoofs, 0);
+        if (any(lessThan(texel, ivec2(0))) || any(greaterThanEqual(texel, size))) {
+            return uSeaLevel;
         }
-        else { horizonColor = nightHorizon; }
+        return texelFetch(uRoofs, texel, 0).r;
+    }
 
-        float fogFactor = 1.0 - exp(-dist * 0.02);
-        fogFactor = clamp(fogFactor, 0.0, 1.0);
-        
-        vec3 finalColor = mix(lighting, horizonColor, fogFactor);
+    void main() {
+        float id = float(gl_VertexID / 6);
+        vec2 corner = CORNERS[gl_VertexID % 6];
+        vec3 seed = vec3(random(id * 3.1), random(id * 5.7 + 1.3), random(id * 7.9 + 2.1));
 
-        FragColor = vec4(finalColor, 1.0);
+        vec3 p = seed * uBoxSize + uOffset;
+        if (uSnow == 1) {
+            // Flakes flutter about their path
+            p.xz += sin(uTime * vec2(1.3, 0.9) + seed.xz * 6.283) * 0.6;
+        }
+        vec3 rel = mod(p - uCameraPos, uBoxSize) - uBoxSize * 0.5;
+        p = uCameraPos + rel;
+
+        // Nothing falls through a roof
+        if (p.y < floorHeight(p)) {
+            gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+            Coord = vec2(0.0);
+            Fade = 0.0;
+            return;
+        }
+
+        vec3 toCamera = normalize(uCameraPos - p);
+        vec3 pos;
+        if (uSnow == 1) {
+            vec3 right = normalize(cross(vec3(0.0, 1.0, 0.0), toCamera));
+            vec3 up = cross(toCamera, right);
+            pos = p + (right * corner.x + up * corner.y) * uSize;
+        } else {
+            // A thin quad from where the drop was a moment ago to where it is
+            vec3 tail = p - uRelativeVelocity * uStreakTime;
+            vec3 along = p - tail;
+            vec3 dir = length(along) > 1e-4 ? normalize(along) : vec3(0.0, 1.0, 0.0);
+            vec3 side = normalize(cross(dir, toCamera));
+            pos = mix(tail, p, corner.y * 0.5 + 0.5) + side * corner.x * uSize;
+        }
+
+        Coord = corner;
+        // Thin out towards the sides of the box, so wrapping doesn't pop
+        vec3 edge = abs(rel) / (uBoxSize * 0.5);
+        Fade = 1.0 - smoothstep(0.7, 1.0, max(max(edge.x, edge.y), edge.z));
+        gl_Position = uViewProjection * vec4(pos, 1.0);
+    }
+"#;
+
+pub const PRECIPITATION_FRAGMENT_SHADER: &str = r#"
+    #version 330 core
+    in vec2 Coord;
+    in float Fade;
+    out vec4 FragColor;
+
+    // Ambient plus a share of the sun and moon (linear HDR)
+    uniform vec3 uLight;
+    uniform int uSnow;
+
+    void main() {
+        float alpha;
+        vec3 color;
+        if (uSnow == 1) {
+            alpha = 1.0 - smoothstep(0.4, 1.0, length(Coord));
+            color = uLight;
+        } else {
+            // Drops are clear; they mostly catch a faint highlight
+            alpha = (1.0 - abs(Coord.x)) * 0.5;
+            color = uLight;
+        }
+        alpha *= Fade;
+        if (alpha < 0.01) discard;
+        FragColor = vec4(color, alpha);
+    }
+"#;
+
+// Lightning bolts as line strips (see lightning.rs). Each vertex carries its
+// branch's brightness; uIntensity flickers the whole bolt.
+pub const LIGHTNING_VERTEX_SHADER: &str = r#"
+    #version 330 core
+    layout (location = 0) in vec4 aPosBrightness;
+    out float Brightness;
+
+    uniform mat4 uViewProjection;
+
+    void main() {
+        Brightness = aPosBrightness.w;
+        gl_Position = uViewProjection * vec4(aPosBrightness.xyz, 1.0);
+    }
+"#;
+
+pub const LIGHTNING_FRAGMENT_SHADER: &str = r#"
+    #version 330 core
+    in float Brightness;
+    out vec4 FragColor;
+
+    // Linear HDR, far above anything else so it blooms
+    uniform vec3 uColor;
+    uniform float uIntensity;
+
+    void main() {
+        FragColor = vec4(uColor * Brightness * uIntensity, 1.0);
+    }
+"#;
+
+// The water surface at sea level: a square around the camera, generated
+// from gl_VertexID (see water.rs). The terrain dips under it for the sea,
+// the river and the harbours.
+pub const WATER_VERTEX_SHADER: &str = r#"
+    #version 330 core
+    out vec3 WorldPos;
+
+    uniform mat4 uViewProjection;
+    uniform vec3 uCenter;
+    uniform float uHalfSize;
+
+    const vec2 CORNERS[6] = vec2[](
+        vec2(-1.0, -1.0), vec2(-1.0, 1.0), vec2(1.0, 1.0),
+        vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(1.0, -1.0)
+    );
+
+    void main() {
+        vec2 corner = CORNERS[gl_VertexID];
+        WorldPos = uCenter + vec3(corner.x, 0.0, corner.y) * uHalfSize;
+        gl_Position = uViewProjection * vec4(WorldPos, 1.0);
+    }
+"#;
+
+// Ripples scroll across the water; it mirrors the sky overhead, more so at
+// grazing angles, and glints where the sun and moon catch the waves
+pub const WATER_FRAGMENT_SHADER: &str = r#"
+    #version 330 core
+    in vec3 WorldPos;
+    out vec4 FragColor;
+
+    uniform vec3 uSunDir;
+    uniform vec3 uMoonDir;
+    uniform vec3 uCameraPos;
+    uniform float uTime;
+    uniform float uCloudFog;
+    // sRGB, seen looking straight down into the water
+    uniform vec3 uWaterColor;
+
+    // Scene lighting terms (see atmosphere.rs)
+    uniform vec3 uSunLightColor;
+    uniform vec3 uMoonLightColor;
+    uniform vec3 uAmbientLight;
+
+    #include "atmosphere"
+    #include "color"
+    #include "clouds"
+    #include "weather"
+
+    // Slope of a few crossing wave trains, each (direction, wavelength, speed)
+    vec2 waveSlope(vec2 p, float t) {
+        const vec4 WAVES[4] = vec4[](
+            vec4(0.8, 0.6, 9.0, 1.1),
+            vec4(-0.4, 0.9, 5.3, 0.8),
+            vec4(0.2, -1.0, 3.1, 0.6),
+            vec4(-0.9, -0.3, 1.7, 0.45)
+        );
+        vec2 slope = vec2(0.0);
+        for (int i = 0; i < 4; i++) {
+            vec2 dir = normalize(WAVES[i].xy);
+            float k = 6.283 / WAVES[i].z;
+            slope += dir * cos(dot(dir, p) * k + t * WAVES[i].w * 6.283 / sqrt(WAVES[i].z)) * 0.08;
+        }
+        return slope;
+    }
+
+    void main() {
+        vec3 toCamera = uCameraPos - WorldPos;
+        float dist = length(toCamera);
+        vec3 viewDir = toCamera / dist;
+
+        // Far ripples are smaller than a pixel; calm them rather than shimmer
+        float calm = clamp(1.0 - dist / 600.0, 0.15, 1.0);
+        vec2 slope = waveSlope(WorldPos.xz, uTime) * calm;
+        vec3 normal = normalize(vec3(-slope.x, 1.0, -slope.y));
+
+        // The sky above, hazed over by whatever cloud covers it
+        vec3 r = reflect(-viewDir, normal);
+        r.y = max(r.y, 0.02);
+        r = normalize(r);
+        vec3 sky = skyRadiance(WorldPos, r, uSunDir) + airglow(r);
+        sky = mix(sky, uFogColor, uCloudCoverage * 0.7);
+
+        float fresnel = 0.02 + 0.98 * pow(1.0 - max(dot(viewDir, normal), 0.0), 5.0);
+        vec3 body = srgbToLinear(uWaterColor) * uAmbientLight;
+        vec3 color = mix(body, sky, fresnel);
+
+        float sunGlint = pow(max(dot(r, uSunDir), 0.0), 400.0) * cloudShadow(WorldPos, uSunDir);
+        float moonGlint = pow(max(dot(r, uMoonDir), 0.0), 200.0);
+        color += sunGlint * uSunLightColor * 4.0 + moonGlint * uMoonLightColor * 2.0;
+
+        color = applyHaze(color, WorldPos, uCameraPos, uSunDir);
+        float fog = fogDepth(WorldPos, uCameraPos) + dist * uCloudFog;
+        FragColor = vec4(mix(color, uFogColor, 1.0 - exp(-fog)), 1.0);
+    }
+"#;
+
+// Full-screen triangle for post-processing passes, generated from
+// gl_VertexID so no vertex buffer is needed (see hdr.rs)
+pub const POST_VERTEX_SHADER: &str = r#"
+    #version 330 core
+    out vec2 TexCoord;
+
+    void main() {
+        vec2 pos = vec2(float((gl_VertexID << 1) & 2), float(gl_VertexID & 2));
+        TexCoord = pos;
+        gl_Position = vec4(pos * 2.0 - 1.0, 0.0, 1.0);
+    }
+"#;
+
+// 13-tap downsample (Jimenez, "Next Generation Post Processing in Call of
+// Duty"). The first pass also applies a soft threshold, so only bright
+// pixels (the sun, lit windows, glints) feed the bloom chain.
+pub const BLOOM_DOWNSAMPLE_FRAGMENT_SHADER: &str = r#"
+    #version 330 core
+    in vec2 TexCoord;
+    out vec4 FragColor;
+
+    uniform sampler2D uSource;
+    uniform vec2 uTexelSize; // Of the source level
+    uniform int uFirstPass;
+    uniform float uThreshold;
+    uniform float uKnee;
+
+    vec3 sampleAt(vec2 offset) {
+        return texture(uSource, TexCoord + offset * uTexelSize).rgb;
+    }
+
+    vec3 prefilter(vec3 c) {
+        float brightness = max(c.r, max(c.g, c.b));
+        float soft = clamp(brightness - uThreshold + uKnee, 0.0, 2.0 * uKnee);
+        soft = soft * soft / (4.0 * uKnee + 1e-5);
+        float contribution = max(soft, brightness - uThreshold) / max(brightness, 1e-5);
+        return c * contribution;
+    }
+
+    void main() {
+        vec3 a = sampleAt(vec2(-2.0, 2.0));
+        vec3 b = sampleAt(vec2(0.0, 2.0));
+        vec3 c = sampleAt(vec2(2.0, 2.0));
+        vec3 d = sampleAt(vec2(-2.0, 0.0));
+        vec3 e = sampleAt(vec2(0.0, 0.0));
+        vec3 f = sampleAt(vec2(2.0, 0.0));
+        vec3 g = sampleAt(vec2(-2.0, -2.0));
+        vec3 h = sampleAt(vec2(0.0, -2.0));
+        vec3 i = sampleAt(vec2(2.0, -2.0));
+        vec3 j = sampleAt(vec2(-1.0, 1.0));
+        vec3 k = sampleAt(vec2(1.0, 1.0));
+        vec3 l = sampleAt(vec2(-1.0, -1.0));
+        vec3 m = sampleAt(vec2(1.0, -1.0));
+
+        vec3 color = e * 0.125
+                   + (a + c + g + i) * 0.03125
+                   + (b + d + f + h) * 0.0625
+                   + (j + k + l + m) * 0.125;
+
+        if (uFirstPass == 1) {
+            // Clamp first so the sun disk doesn't turn into flickering fireflies
+            color = prefilter(min(color, vec3(64.0)));
+        }
+        FragColor = vec4(color, 1.0);
+    }
+"#;
+
+// 3x3 tent upsample, additively blended onto the next larger level
+pub const BLOOM_UPSAMPLE_FRAGMENT_SHADER: &str = r#"
+    #version 330 core
+    in vec2 TexCoord;
+    out vec4 FragColor;
+
+    uniform sampler2D uSource;
+    uniform vec2 uTexelSize; // Of the source level
+    uniform float uRadius;
+
+    void main() {
+        vec2 r = uTexelSize * uRadius;
+        vec3 color = texture(uSource, TexCoord).rgb * 4.0;
+        color += (texture(uSource, TexCoord + vec2(-r.x, 0.0)).rgb
+                + texture(uSource, TexCoord + vec2(r.x, 0.0)).rgb
+                + texture(uSource, TexCoord + vec2(0.0, -r.y)).rgb
+                + texture(uSource, TexCoord + vec2(0.0, r.y)).rgb) * 2.0;
+        color += texture(uSource, TexCoord + vec2(-r.x, -r.y)).rgb
+               + texture(uSource, TexCoord + vec2(r.x, -r.y)).rgb
+               + texture(uSource, TexCoord + vec2(-r.x, r.y)).rgb
+               + texture(uSource, TexCoord + vec2(r.x, r.y)).rgb;
+        FragColor = vec4(color / 16.0, 1.0);
+    }
+"#;
+
+// Exposure, bloom composite, filmic curve and sRGB encoding
+pub const TONEMAP_FRAGMENT_SHADER: &str = r#"
+    #version 330 core
+    in vec2 TexCoord;
+    out vec4 FragColor;
+
+    uniform sampler2D uScene;
+    uniform sampler2D uBloom;
+    uniform float uExposure;
+    uniform float uBloomStrength;
+
+    #include "color"
+
+    // ACES filmic curve, Narkowicz's fit
+    vec3 aces(vec3 x) {
+        return clamp((x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14), 0.0, 1.0);
+    }
+
+    void main() {
+        vec3 hdr = texture(uScene, TexCoord).rgb;
+        hdr += texture(uBloom, TexCoord).rgb * uBloomStrength;
+        vec3 mapped = aces(hdr * uExposure);
+        FragColor = vec4(linearToSrgb(mapped), 1.0);
+    }
+"#;
+
+// Post-process chain (see post.rs). These run after tonemapping, on
+// display-referred sRGB colour.
+
+// Radial blur towards the point the camera is flying at, scaled by speed
+pub const MOTION_BLUR_FRAGMENT_SHADER: &str = r#"
+    #version 330 core
+    in vec2 TexCoord;
+    out vec4 FragColor;
+
+    uniform sampler2D uSource;
+    uniform vec2 uFocus;
+    uniform float uStrength;
+
+    const int SAMPLES = 8;
+
+    void main() {
+        vec2 offset = (TexCoord - uFocus) * uStrength;
+        vec3 color = vec3(0.0);
+        for (int i = 0; i < SAMPLES; i++) {
+            color += texture(uSource, TexCoord - offset * (float(i) / float(SAMPLES - 1))).rgb;
+        }
+        FragColor = vec4(color / float(SAMPLES), 1.0);
+    }
+"#;
+
+// Looks colours up in a 3D LUT stored as a horizontal strip of blue slices
+pub const COLOR_GRADING_FRAGMENT_SHADER: &str = r#"
+    #version 330 core
+    in vec2 TexCoord;
+    out vec4 FragColor;
+
+    uniform sampler2D uSource;
+    uniform sampler2D uLut;
+    uniform float uLutSize;
+    uniform float uIntensity;
+
+    vec3 lookup(vec3 color) {
+        float n = uLutSize;
+        vec3 scaled = clamp(color, 0.0, 1.0) * (n - 1.0);
+        float slice = floor(scaled.b);
+        float t = scaled.b - slice;
+        vec2 uv = vec2((scaled.r + 0.5 + slice * n) / (n * n), (scaled.g + 0.5) / n);
+        vec3 a = texture(uLut, uv).rgb;
+        vec3 b = texture(uLut, uv + vec2(1.0 / n, 0.0)).rgb;
+        return mix(a, b, t);
+    }
+
+    void main() {
+        vec3 color = texture(uSource, TexCoord).rgb;
+        FragColor = vec4(mix(color, lookup(color), uIntensity), 1.0);
+    }
+"#;
+
+pub const VIGNETTE_FRAGMENT_SHADER: &str = r#"
+    #version 330 core
+    in vec2 TexCoord;
+    out vec4 FragColor;
+
+    uniform sampler2D uSource;
+    uniform float uStrength;
+    uniform float uAspect;
+
+    void main() {
+        // 0 at the centre, 1 in the corners, round regardless of aspect
+        vec2 p = (TexCoord - 0.5) * vec2(uAspect, 1.0);
+        float d = length(p) / length(vec2(uAspect, 1.0) * 0.5);
+        float falloff = 1.0 - uStrength * smoothstep(0.4, 1.0, d);
+        FragColor = vec4(texture(uSource, TexCoord).rgb * falloff, 1.0);
+    }
+"#;
+
+// FXAA after Lottes: blends along the local edge direction where luma contrast
+// is high, which also softens the aliasing of the procedural window grid
+pub const FXAA_FRAGMENT_SHADER: &str = r#"
+    #version 330 core
+    in vec2 TexCoord;
+    out vec4 FragColor;
+
+    uniform sampler2D uSource;
+    uniform vec2 uTexelSize;
+
+    #include "color"
+
+    const float REDUCE_MIN = 1.0 / 128.0;
+    const float REDUCE_MUL = 1.0 / 8.0;
+    const float SPAN_MAX = 8.0;
+
+    vec3 sampleAt(vec2 uv) {
+        return texture(uSource, uv).rgb;
+    }
+
+    void main() {
+        vec3 rgbM = sampleAt(TexCoord);
+        float lumaNW = luminance(sampleAt(TexCoord + vec2(-1.0, -1.0) * uTexelSize));
+        float lumaNE = luminance(sampleAt(TexCoord + vec2(1.0, -1.0) * uTexelSize));
+        float lumaSW = luminance(sampleAt(TexCoord + vec2(-1.0, 1.0) * uTexelSize));
+        float lumaSE = luminance(sampleAt(TexCoord + vec2(1.0, 1.0) * uTexelSize));
+        float lumaM = luminance(rgbM);
+        float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+        float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+        vec2 dir = vec2(-((lumaNW + lumaNE) - (lumaSW + lumaSE)), (lumaNW + lumaSW) - (lumaNE + lumaSE));
+        float dirReduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+        float rcpDirMin = 1.0 / (min(abs(dir.x), abs(dir.y)) + dirReduce);
+        dir = clamp(dir * rcpDirMin, vec2(-SPAN_MAX), vec2(SPAN_MAX)) * uTexelSize;
+
+        vec3 rgbA = 0.5 * (sampleAt(TexCoord + dir * (1.0 / 3.0 - 0.5)) + sampleAt(TexCoord + dir * (2.0 / 3.0 - 0.5)));
+        vec3 rgbB = rgbA * 0.5 + 0.25 * (sampleAt(TexCoord - dir * 0.5) + sampleAt(TexCoord + dir * 0.5));
+        float lumaB = luminance(rgbB);
+        FragColor = vec4((lumaB < lumaMin || lumaB > lumaMax) ? rgbA : rgbB, 1.0);
+    }
+"#;
+
+// Debug program used when one of the shaders above fails to build
+pub const FALLBACK_VERTEX_SHADER: &str = r#"
+    #version 330 core
+    layout (location = 0) in vec3 aPos;
+    layout (location = 1) in mat4 aModel;
+
+    uniform mat4 view;
+    uniform mat4 projection;
+
+    void main() {
+        gl_Position = projection * view * aModel * vec4(aPos, 1.0);
+    }
+"#;
+
+pub const FALLBACK_FRAGMENT_SHADER: &str = r#"
+    #version 330 core
+    out vec4 FragColor;
+
+    void main() {
+        FragColor = vec4(1.0, 0.0, 1.0, 1.0);
     }
 "#;
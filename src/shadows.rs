@@ -0,0 +1,197 @@
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::chunks::{ChunkCache, ChunkCoord};
+use crate::culling::Frustum;
+use crate::gl_utils::{Framebuffer, ShaderProgram};
+use crate::hot_reload::ReloadableProgram;
+use crate::shaders::{SHADOW_FRAGMENT_SHADER, SHADOW_VERTEX_SHADER};
+
+// Must match SUN_CASCADES in SHADOWS_GLSL
+pub const SUN_CASCADES: usize = 3;
+const CASCADE_SIZE: i32 = 2048;
+const MOON_MAP_SIZE: i32 = 1024;
+
+// Sun shadows reach this far from the camera
+const SHADOW_DISTANCE: f32 = 600.0;
+// Blend between uniform (0) and logarithmic (1) cascade splits
+const SPLIT_LAMBDA: f32 = 0.75;
+// The moon's single map is centred on the camera
+const MOON_SHADOW_RADIUS: f32 = 250.0;
+// How far towards the light from each cascade casters are still drawn, so
+// distant towers throw their long sunrise shadows into view
+const CASTER_RANGE: f32 = 2000.0;
+// Below this the light is too weak to be worth a shadow pass
+const MIN_LIGHT_HEIGHT: f32 = -0.02;
+
+// What the shadow pass needs to know about the camera
+pub struct ShadowCamera {
+    pub position: Vec3,
+    pub view: Mat4,
+    pub fov_y: f32,
+    pub aspect: f32,
+    pub near: f32,
+}
+
+// One light's view of the scene
+#[derive(Clone, Copy)]
+struct LightView {
+    view_projection: Mat4,
+    // World units per shadow texel
+    texel: f32,
+}
+
+impl LightView {
+    // Maps everything onto a cleared map, so it's all lit
+    const UNLIT: LightView = LightView { view_projection: Mat4::ZERO, texel: 0.0 };
+
+    // Orthographic view along `light_dir` covering a sphere, snapped to whole
+    // texels so the shadow edges don't shimmer as the camera moves
+    fn fit(center: Vec3, radius: f32, light_dir: Vec3, resolution: i32) -> Self {
+        let up = if light_dir.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+        let eye = center + light_dir * CASTER_RANGE;
+        let view = Mat4::look_at_rh(eye, center, up);
+        let projection = Mat4::orthographic_rh_gl(-radius, radius, -radius, radius, 0.0, CASTER_RANGE + radius);
+
+        let texels = resolution as f32 * 0.5;
+        let origin = (projection * view) * Vec4::W;
+        let snapped = (origin.truncate().truncate() * texels).round() / texels;
+        let offset = snapped - origin.truncate().truncate();
+        let snap = Mat4::from_translation(offset.extend(0.0));
+
+        Self { view_projection: snap * projection * view, texel: 2.0 * radius / resolution as f32 }
+    }
+
+    // World position to [0, 1] texture coordinates and depth
+    fn texture_matrix(&self) -> Mat4 {
+        Mat4::from_translation(Vec3::splat(0.5)) * Mat4::from_scale(Vec3::splat(0.5)) * self.view_projection
+    }
+}
+
+// View distances bounding each cascade (PSSM practical split scheme)
+fn cascade_splits(near: f32, far: f32) -> [f32; SUN_CASCADES + 1] {
+    let mut splits = [near; SUN_CASCADES + 1];
+    for (i, split) in splits.iter_mut().enumerate().skip(1) {
+        let t = i as f32 / SUN_CASCADES as f32;
+        let log = near * (far / near).powf(t);
+        let uniform = near + (far - near) * t;
+        *split = uniform + (log - uniform) * SPLIT_LAMBDA;
+    }
+    splits
+}
+
+// Bounding sphere of the camera frustum between two view distances
+fn slice_sphere(camera: &ShadowCamera, near: f32, far: f32) -> (Vec3, f32) {
+    let camera_to_world = camera.view.inverse();
+    let tan_y = (camera.fov_y * 0.5).tan();
+    let tan_x = tan_y * camera.aspect;
+    let corners: Vec<Vec3> = [near, far].iter().flat_map(|&d| {
+        [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)]
+            .map(|(x, y)| camera_to_world.transform_point3(Vec3::new(x * tan_x * d, y * tan_y * d, -d)))
+    }).collect();
+    let center = corners.iter().sum::<Vec3>() / corners.len() as f32;
+    let radius = corners.iter().map(|c| c.distance(center)).fold(0.0, f32::max);
+    // Keep the size steady as the camera turns, so the texel snapping holds
+    (center, (radius * 16.0).ceil() / 16.0)
+}
+
+// Cascaded shadow maps for the sun and a single map for the moon
+pub struct ShadowMaps {
+    // Sun cascades side by side
+    sun_atlas: Framebuffer,
+    sun_views: [LightView; SUN_CASCADES],
+    moon_map: Framebuffer,
+    moon_view: LightView,
+    program: ReloadableProgram,
+    pub draw_calls: u32,
+}
+
+impl ShadowMaps {
+    pub unsafe fn new() -> Self {
+        let mut max_size = 0;
+        gl::GetIntegerv(gl::MAX_TEXTURE_SIZE, &mut max_size);
+        let cascade_size = CASCADE_SIZE.min(max_size / SUN_CASCADES as i32);
+
+        let sun_atlas = Framebuffer::depth_only(cascade_size * SUN_CASCADES as i32, cascade_size).expect("sun shadow atlas");
+        let moon_map = Framebuffer::depth_only(MOON_MAP_SIZE, MOON_MAP_SIZE).expect("moon shadow map");
+        // Start fully lit, in case a light never gets a pass
+        for map in [&sun_atlas, &moon_map] {
+            map.bind();
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        Self {
+            sun_atlas,
+            sun_views: [LightView::UNLIT; SUN_CASCADES],
+            moon_map,
+            moon_view: LightView::UNLIT,
+            program: ReloadableProgram::new("shadow", SHADOW_VERTEX_SHADER, SHADOW_FRAGMENT_SHADER),
+            draw_calls: 0,
+        }
+    }
+
+    // Renders the sun cascades and the moon map. Leaves the shadow
+    // framebuffer bound; the caller rebinds its own target.
+    pub unsafe fn render(&mut self, chunks: &ChunkCache, center: ChunkCoord, camera: &ShadowCamera, sun_dir: Vec3, moon_dir: Vec3) {
+        self.program.poll();
+        let program = self.program.program();
+        program.bind();
+        self.draw_calls = 0;
+
+        gl::Enable(gl::POLYGON_OFFSET_FILL);
+        gl::PolygonOffset(1.5, 2.0);
+
+        if sun_dir.y > MIN_LIGHT_HEIGHT {
+            let cascade_size = self.sun_atlas.height;
+            self.sun_atlas.bind();
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+            let splits = cascade_splits(camera.near, SHADOW_DISTANCE);
+            for i in 0..SUN_CASCADES {
+                let (sphere_center, radius) = slice_sphere(camera, splits[i], splits[i + 1]);
+                let light = LightView::fit(sphere_center, radius, sun_dir, cascade_size);
+                gl::Viewport(i as i32 * cascade_size, 0, cascade_size, cascade_size);
+                self.draw_calls += draw_casters(program, &light, chunks, center);
+                self.sun_views[i] = light;
+            }
+        } else if self.sun_views[0].texel != 0.0 {
+            // Left as they were, the views would keep casting the last
+            // shadows of the day
+            self.sun_atlas.bind();
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+            self.sun_views = [LightView::UNLIT; SUN_CASCADES];
+        }
+
+        if moon_dir.y > MIN_LIGHT_HEIGHT {
+            self.moon_map.bind();
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+            let light = LightView::fit(camera.position, MOON_SHADOW_RADIUS, moon_dir, MOON_MAP_SIZE);
+            self.draw_calls += draw_casters(program, &light, chunks, center);
+            self.moon_view = light;
+        } else if self.moon_view.texel != 0.0 {
+            self.moon_map.bind();
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+            self.moon_view = LightView::UNLIT;
+        }
+
+        gl::Disable(gl::POLYGON_OFFSET_FILL);
+    }
+
+    // Binds the maps to `first_unit` and the one after it
+    pub unsafe fn apply(&self, program: &ShaderProgram, first_unit: u32) {
+        self.sun_atlas.depth().bind(first_unit);
+        self.moon_map.depth().bind(first_unit + 1);
+        gl::ActiveTexture(gl::TEXTURE0);
+
+        program.set_i32("uSunShadowMap", first_unit as i32);
+        program.set_mat4_array("uSunShadowMatrices", &self.sun_views.map(|v| v.texture_matrix()));
+        program.set_f32_array("uSunShadowTexels", &self.sun_views.map(|v| v.texel));
+        program.set_i32("uMoonShadowMap", first_unit as i32 + 1);
+        program.set_mat4("uMoonShadowMatrix", &self.moon_view.texture_matrix());
+        program.set_f32("uMoonShadowTexel", self.moon_view.texel);
+    }
+}
+
+unsafe fn draw_casters(program: &ShaderProgram, light: &LightView, chunks: &ChunkCache, center: ChunkCoord) -> u32 {
+    program.set_mat4("uLightViewProjection", &light.view_projection);
+    chunks.draw_shadow_casters(center, &Frustum::from_matrix(light.view_projection))
+}
@@ -0,0 +1,221 @@
+use std::sync::OnceLock;
+
+use glam::{Vec2, Vec3};
+
+use crate::culling::Aabb;
+use crate::mesh::MeshBuilder;
+use crate::noise::{fbm, noise};
+
+// The datum altitudes are measured from: the coastline, and the height fog
+// pools and the wind profile start at
+pub const SEA_LEVEL: f32 = -10.0;
+// Grass; the scene shader turns steep slopes to rock and the shore to sand
+pub const TERRAIN_COLOR: Vec3 = Vec3::new(0.42, 0.52, 0.30);
+
+// Picks the plane through the noise lattice the landscape is cut from.
+// Another seed grows another coastline and other hills.
+pub const DEFAULT_SEED: u16 = 47;
+// Land and sea vary over this many units, hills over this many
+const COAST_SCALE: f32 = 1.0 / 3000.0;
+const HILL_SCALE: f32 = 1.0 / 900.0;
+// Continent field value along the shore. Above it the land rises gently
+// inland, below it the seabed falls away to SEABED_DEPTH.
+const COASTLINE: f32 = 0.36;
+const INLAND_RISE: f32 = 120.0;
+const SEABED_DEPTH: f32 = 30.0;
+// Tallest hills above the plain. They flatten out towards the shore, and
+// the lowest part of the hill field is left as flat valley floor.
+const HILL_HEIGHT: f32 = 90.0;
+const HILL_SHORE_FADE: f32 = 0.08;
+const VALLEY_FLOOR: f32 = 0.35;
+const HILL_CREST: f32 = 0.75;
+// The river winds north to south, swinging this far either side of its
+// mean course over the meander scale. Its channel is cut down to
+// CHANNEL_BED, with banks no steeper than RIVER_BANK_SLOPE.
+const RIVER_X: f32 = 420.0;
+const RIVER_MEANDER: f32 = 260.0;
+const RIVER_MEANDER_SCALE: f32 = 1.0 / 1400.0;
+const RIVER_HALF_WIDTH: f32 = 24.0;
+const RIVER_BANK_SLOPE: f32 = 0.6;
+// Harbour basins are cut into the coastal strip where their own noise field
+// peaks, reaching this far inland in continent field units
+const HARBOUR_SCALE: f32 = 1.0 / 260.0;
+const HARBOUR_REACH: f32 = 0.06;
+// Floor of the river and the harbours, flooded by the sea
+const CHANNEL_BED: f32 = SEA_LEVEL - 6.0;
+// Tile edges hang this far down, hiding cracks between levels of detail
+const SKIRT_DEPTH: f32 = 20.0;
+
+static SEED: OnceLock<u16> = OnceLock::new();
+
+// Chooses the world. Call it before anything asks for the ground; the first
+// seed set is kept, since chunks already generated can't follow a new one.
+pub fn set_seed(seed: u16) {
+    let _ = SEED.set(seed);
+}
+
+fn seed_plane() -> f32 {
+    f32::from(SEED.get().copied().unwrap_or(DEFAULT_SEED))
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Horizontal distance from (x, z) to the middle of the river
+fn river_distance(x: f32, z: f32) -> f32 {
+    let seed = seed_plane();
+    let course = |z: f32| RIVER_X + RIVER_MEANDER * (noise(Vec3::new(z * RIVER_MEANDER_SCALE, seed, 0.5)) * 2.0 - 1.0);
+    // Across the channel rather than along x, where it runs at an angle
+    let slope = (course(z + 1.0) - course(z - 1.0)) * 0.5;
+    (x - course(z)).abs() / (1.0 + slope * slope).sqrt()
+}
+
+// Height of the ground at (x, z); below SEA_LEVEL out at sea, in the river
+// and in the harbours
+pub fn height_at(x: f32, z: f32) -> f32 {
+    let seed = seed_plane();
+    let at = |scale: f32| Vec3::new(x * scale, seed, z * scale);
+    let shore = fbm(at(COAST_SCALE)) - COASTLINE;
+    let plain = SEA_LEVEL + (shore * INLAND_RISE).max(-SEABED_DEPTH);
+    let hills = smoothstep(VALLEY_FLOOR, HILL_CREST, noise(at(HILL_SCALE)) * 0.7 + fbm(at(HILL_SCALE * 3.0)) * 0.35);
+    let land = plain + hills * HILL_HEIGHT * smoothstep(0.0, HILL_SHORE_FADE, shore);
+    if land <= CHANNEL_BED {
+        return land;
+    }
+
+    // Harbour basins, only along the shore
+    let basin = smoothstep(0.55, 0.7, noise(at(HARBOUR_SCALE) + Vec3::Y * 0.5)) * (1.0 - smoothstep(HARBOUR_REACH * 0.5, HARBOUR_REACH, shore));
+    let land = CHANNEL_BED + (land - CHANNEL_BED) * (1.0 - basin);
+    // The river valley's walls, down to its bed
+    let valley = CHANNEL_BED + (river_distance(x, z) - RIVER_HALF_WIDTH).max(0.0) * RIVER_BANK_SLOPE;
+    land.min(valley)
+}
+
+// Upward surface normal at (x, z)
+pub fn normal_at(x: f32, z: f32) -> Vec3 {
+    let e = 1.0;
+    let dx = height_at(x + e, z) - height_at(x - e, z);
+    let dz = height_at(x, z + e) - height_at(x, z - e);
+    Vec3::new(-dx, 2.0 * e, -dz).normalize()
+}
+
+// A square of terrain `size` units across from `min`, as a grid of `quads`
+// by `quads` cells in world space, with a skirt around its edges. Returns
+// the mesh and its bounds.
+pub fn tile_mesh(min: Vec2, size: f32, quads: u32) -> (MeshBuilder, Aabb) {
+    let mut builder = MeshBuilder::new();
+    let step = size / quads as f32;
+    let row = quads + 1;
+    let mut low = f32::INFINITY;
+    let mut high = f32::NEG_INFINITY;
+
+    let mut point = |i: u32, j: u32, builder: &mut MeshBuilder, drop: f32| {
+        let (x, z) = (min.x + i as f32 * step, min.y + j as f32 * step);
+        let y = height_at(x, z);
+        low = low.min(y - drop);
+        high = high.max(y);
+        let normal = normal_at(x, z);
+        let tangent = (Vec3::X - normal * normal.x).normalize().extend(-1.0);
+        builder.vertex(Vec3::new(x, y - drop, z), normal, Vec2::new(x, z), tangent)
+    };
+
+    for j in 0..row {
+        for i in 0..row {
+            point(i, j, &mut builder, 0.0);
+        }
+    }
+    // Counter-clockwise seen from above
+    for j in 0..quads {
+        for i in 0..quads {
+            let a = j * row + i;
+            builder.triangle(a, a + row, a + row + 1).triangle(a, a + row + 1, a + 1);
+        }
+    }
+
+    // Each edge walked with the tile on its left, hung from the grid
+    let last = quads;
+    let edge_cell = |edge: u32, k: u32| match edge {
+        0 => (k, 0),
+        1 => (last, k),
+        2 => (last - k, last),
+        _ => (0, last - k),
+    };
+    for edge in 0..4 {
+        let bottom: Vec<u32> = (0..=quads).map(|k| {
+            let (i, j) = edge_cell(edge, k);
+            point(i, j, &mut builder, SKIRT_DEPTH)
+        }).collect();
+        for k in 0..quads as usize {
+            let (i0, j0) = edge_cell(edge, k as u32);
+            let (i1, j1) = edge_cell(edge, k as u32 + 1);
+            let (top0, top1) = (j0 * row + i0, j1 * row + i1);
+            builder.triangle(top0, bottom[k], bottom[k + 1]).triangle(top0, bottom[k + 1], top1);
+        }
+    }
+
+    let bounds = Aabb { min: Vec3::new(min.x, low, min.y), max: Vec3::new(min.x + size, high, min.y + size) };
+    (builder, bounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> impl Iterator<Item = (f32, f32)> {
+        (-60..60).flat_map(|x| (-60..60).map(move |z| (x as f32 * 97.0, z as f32 * 97.0)))
+    }
+
+    #[test]
+    fn land_and_sea_both_have_their_share() {
+        let heights: Vec<f32> = samples().map(|(x, z)| height_at(x, z)).collect();
+        let sea = heights.iter().filter(|&&h| h < SEA_LEVEL).count() as f32 / heights.len() as f32;
+        assert!((0.1..0.5).contains(&sea), "sea covers {:.2}", sea);
+        let hills = heights.iter().filter(|&&h| h > SEA_LEVEL + 50.0).count() as f32 / heights.len() as f32;
+        assert!(hills > 0.05, "hills cover {:.2}", hills);
+        assert!(heights.iter().all(|&h| (SEA_LEVEL - SEABED_DEPTH..SEA_LEVEL + INLAND_RISE + HILL_HEIGHT).contains(&h)));
+        // The flight starts over dry land
+        assert!(height_at(0.0, 0.0) > SEA_LEVEL);
+    }
+
+    #[test]
+    fn the_river_cuts_through_the_hills() {
+        let mut valleys = 0;
+        for z in (-4000..4000).step_by(50) {
+            let z = z as f32;
+            let xs = (-1000..2000).map(|x| x as f32);
+            let middle = xs.min_by(|&a, &b| river_distance(a, z).total_cmp(&river_distance(b, z))).unwrap();
+            // Always flooded, however high the land around it
+            assert!(height_at(middle, z) < SEA_LEVEL);
+            if height_at(middle - 200.0, z) > SEA_LEVEL + 40.0 && height_at(middle + 200.0, z) > SEA_LEVEL + 40.0 {
+                valleys += 1;
+            }
+        }
+        assert!(valleys > 5, "only {} rows in a valley", valleys);
+    }
+
+    #[test]
+    fn ground_has_no_cliffs() {
+        for (x, z) in samples() {
+            // Nowhere steeper than 60 degrees
+            assert!(normal_at(x, z).y > 0.5, "cliff at {}, {}", x, z);
+        }
+    }
+
+    #[test]
+    fn tiles_bound_their_ground() {
+        let (min, size) = (Vec2::new(192.0, -384.0), 192.0);
+        let (_, bounds) = tile_mesh(min, size, 8);
+        assert_eq!((bounds.min.x, bounds.min.z, bounds.max.x, bounds.max.z), (192.0, -384.0, 384.0, -192.0));
+        // Between the grid points too, and with room for the skirts below
+        for i in 0..=32 {
+            for j in 0..=32 {
+                let (x, z) = (min.x + i as f32 * 6.0, min.y + j as f32 * 6.0);
+                let y = height_at(x, z);
+                assert!(y < bounds.max.y + 5.0 && y - SKIRT_DEPTH >= bounds.min.y - 5.0);
+            }
+        }
+        assert!(bounds.max.y - bounds.min.y >= SKIRT_DEPTH);
+    }
+}
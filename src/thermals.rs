@@ -0,0 +1,94 @@
+use glam::Vec3;
+
+use crate::terrain::SEA_LEVEL;
+use crate::world::{get_building_info, hash, is_road, lot_ground, GRID_SPACING};
+
+// Climb rate (units per second) in the core of the strongest thermal at full sun
+const THERMAL_LIFT: f32 = 4.0;
+// Columns are this wide at every height
+const THERMAL_RADIUS: f32 = 6.0;
+// Share of rooftops warm enough to start a thermal. Every empty lot does.
+const ROOF_THERMAL_CHANCE: u64 = 30;
+// Rising air gathers strength over this height above its source, and
+// spreads out and stops over this distance below the cloud base
+const THERMAL_RAMP: f32 = 10.0;
+const THERMAL_TOP_FADE: f32 = 40.0;
+
+// How strongly the sun heats the city, 0..1: nothing at night, weak with the
+// sun low or behind overcast
+pub fn solar_heating(sun_dir: Vec3, overcast: f32) -> f32 {
+    let t = (sun_dir.y / 0.6).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t) * (1.0 - 0.8 * overcast)
+}
+
+// Where the thermal of a cell starts and its strength relative to others,
+// if it has one. Open lots bake in the sun; only some roofs do, and the
+// sea stays cool.
+fn source(x: i32, z: i32) -> Option<(f32, f32)> {
+    let ground = lot_ground(x, z);
+    if is_road(x, z) || ground < SEA_LEVEL {
+        return None;
+    }
+    let h = hash(x, z ^ 0x7411);
+    let strength = (h % 1000) as f32 / 1000.0;
+    match get_building_info(x, z) {
+        None => Some((ground, 0.6 + 0.4 * strength)),
+        Some((height, _)) if (h >> 16) % 100 < ROOF_THERMAL_CHANCE => Some((ground + height, 0.4 + 0.3 * strength)),
+        Some(_) => None,
+    }
+}
+
+// Upward speed of the air at p, which rises in columns from its source up to
+// the cloud base
+pub fn thermal_lift(p: Vec3, heating: f32, cloud_base: f32) -> f32 {
+    if heating <= 0.0 || p.y > cloud_base {
+        return 0.0;
+    }
+    let cx = (p.x / GRID_SPACING).round() as i32;
+    let cz = (p.z / GRID_SPACING).round() as i32;
+    let top = ((cloud_base - p.y) / THERMAL_TOP_FADE).clamp(0.0, 1.0);
+
+    let mut lift: f32 = 0.0;
+    for z in cz - 1..=cz + 1 {
+        for x in cx - 1..=cx + 1 {
+            let Some((base, strength)) = source(x, z) else { continue };
+            let r = ((p.x - x as f32 * GRID_SPACING).powi(2) + (p.z - z as f32 * GRID_SPACING).powi(2)).sqrt() / THERMAL_RADIUS;
+            if r >= 1.0 || p.y < base {
+                continue;
+            }
+            let ramp = ((p.y - base) / THERMAL_RAMP).min(1.0);
+            lift = lift.max(strength * (1.0 - r * r).powi(2) * ramp);
+        }
+    }
+    lift * top * heating * THERMAL_LIFT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::is_buildable;
+
+    fn empty_lot() -> Vec3 {
+        let (x, z) = (0..100).flat_map(|x| (0..100).map(move |z| (x, z))).find(|&(x, z)| is_buildable(x, z) && get_building_info(x, z).is_none()).unwrap();
+        Vec3::new(x as f32 * GRID_SPACING, lot_ground(x, z) + 60.0, z as f32 * GRID_SPACING)
+    }
+
+    #[test]
+    fn lots_rise_under_the_sun() {
+        let noon = solar_heating(Vec3::Y, 0.0);
+        let lot = empty_lot();
+        let core = thermal_lift(lot, noon, 150.0);
+        assert!(core > 0.5 * THERMAL_LIFT);
+        assert!(thermal_lift(lot + Vec3::new(THERMAL_RADIUS * 0.7, 0.0, 0.0), noon, 150.0) < core);
+        // Stops at the cloud base
+        assert_eq!(thermal_lift(Vec3::new(lot.x, 151.0, lot.z), noon, 150.0), 0.0);
+    }
+
+    #[test]
+    fn nothing_at_night() {
+        let night = solar_heating(Vec3::new(0.3, -0.5, 0.0).normalize(), 0.0);
+        assert_eq!(night, 0.0);
+        assert_eq!(thermal_lift(empty_lot(), night, 150.0), 0.0);
+        assert!(solar_heating(Vec3::Y, 1.0) < solar_heating(Vec3::Y, 0.0));
+    }
+}
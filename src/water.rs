@@ -0,0 +1,67 @@
+use glam::{Mat4, Vec3};
+
+use crate::atmosphere::SkyPalette;
+use crate::hot_reload::ReloadableProgram;
+use crate::shaders::{WATER_FRAGMENT_SHADER, WATER_VERTEX_SHADER};
+use crate::terrain::SEA_LEVEL;
+use crate::weather::Weather;
+
+// sRGB, looking straight down into deep water
+const WATER_COLOR: Vec3 = Vec3::new(0.10, 0.22, 0.28);
+
+// Per-frame inputs besides the weather and the sky's light
+#[derive(Clone, Copy, Debug)]
+pub struct WaterFrame {
+    pub camera: Vec3,
+    // The water reaches this far around the camera
+    pub draw_distance: f32,
+    pub time: f32,
+    pub sun_dir: Vec3,
+    pub moon_dir: Vec3,
+    // Fog extinction when the camera is inside a cloud, 0 in clear air
+    pub cloud_fog: f32,
+}
+
+// The sea, the river and the harbours: one sheet of water at sea level
+// following the camera, seen wherever the terrain dips below it
+pub struct Water {
+    program: ReloadableProgram,
+    vao: u32,
+}
+
+impl Water {
+    pub unsafe fn new() -> Self {
+        let mut vao = 0;
+        gl::GenVertexArrays(1, &mut vao);
+        Self { program: ReloadableProgram::new("water", WATER_VERTEX_SHADER, WATER_FRAGMENT_SHADER), vao }
+    }
+
+    // Draws into the bound HDR target with the opaque scene
+    pub unsafe fn draw(&mut self, view_projection: &Mat4, frame: &WaterFrame, weather: &Weather, palette: &SkyPalette) {
+        self.program.poll();
+        let program = self.program.program();
+        program.bind();
+        program.set_mat4("uViewProjection", view_projection);
+        program.set_vec3("uCenter", Vec3::new(frame.camera.x, SEA_LEVEL, frame.camera.z));
+        program.set_f32("uHalfSize", frame.draw_distance);
+        program.set_vec3("uCameraPos", frame.camera);
+        program.set_f32("uSeaLevel", SEA_LEVEL);
+        program.set_vec3("uSunDir", frame.sun_dir);
+        program.set_vec3("uMoonDir", frame.moon_dir);
+        program.set_f32("uTime", frame.time);
+        program.set_f32("uCloudFog", frame.cloud_fog);
+        program.set_vec3("uWaterColor", WATER_COLOR);
+        weather.apply(program);
+        palette.apply_scene(program);
+
+        gl::BindVertexArray(self.vao);
+        gl::DrawArrays(gl::TRIANGLES, 0, 6);
+        gl::BindVertexArray(0);
+    }
+}
+
+impl Drop for Water {
+    fn drop(&mut self) {
+        unsafe { gl::DeleteVertexArrays(1, &self.vao) };
+    }
+}
@@ -0,0 +1,274 @@
+use std::str::FromStr;
+
+use glam::Vec3;
+
+use crate::clouds::CloudLayer;
+use crate::gl_utils::ShaderProgram;
+use crate::wind::ambient_wind;
+use crate::terrain::SEA_LEVEL;
+use crate::world::hash;
+
+// Visibility in air with no fog at all; the haze in the atmosphere shader
+// takes over from here
+pub const CLEAR_VISIBILITY: f32 = 20000.0;
+// Fog pools at sea level and in the valleys, thinning out by a factor of e
+// every this many units above the sea
+const FOG_HEIGHT: f32 = 40.0;
+// Seconds for a change of weather to get most of the way there
+const TRANSITION_TIME: f32 = 20.0;
+// How long each kind of weather lasts when it changes by itself
+const WEATHER_PERIOD: f32 = 180.0;
+// Seconds for rain to soak the city, and for it to dry out again
+const WETTING_TIME: f32 = 15.0;
+const DRYING_TIME: f32 = 90.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WeatherKind {
+    Clear,
+    Fair,
+    Cloudy,
+    Overcast,
+    Fog,
+    Storm,
+    Snow,
+}
+
+impl WeatherKind {
+    pub const ALL: [WeatherKind; 7] = [
+        WeatherKind::Clear,
+        WeatherKind::Fair,
+        WeatherKind::Cloudy,
+        WeatherKind::Overcast,
+        WeatherKind::Fog,
+        WeatherKind::Storm,
+        WeatherKind::Snow,
+    ];
+
+    pub fn state(self) -> WeatherState {
+        let (coverage, cloud_bottom, cloud_top, wind, visibility, precipitation, snow) = match self {
+            WeatherKind::Clear => (0.15, 180.0, 260.0, Vec3::new(6.0, 0.0, 2.0), CLEAR_VISIBILITY, 0.0, 0.0),
+            WeatherKind::Fair => (0.4, 150.0, 250.0, Vec3::new(10.0, 0.0, 4.0), CLEAR_VISIBILITY, 0.0, 0.0),
+            WeatherKind::Cloudy => (0.6, 140.0, 260.0, Vec3::new(12.0, 0.0, 6.0), 12000.0, 0.0, 0.0),
+            WeatherKind::Overcast => (0.9, 120.0, 260.0, Vec3::new(14.0, 0.0, 6.0), 6000.0, 0.1, 0.0),
+            WeatherKind::Fog => (0.5, 150.0, 250.0, Vec3::new(3.0, 0.0, 1.0), 300.0, 0.0, 0.0),
+            WeatherKind::Storm => (1.0, 110.0, 280.0, Vec3::new(22.0, 0.0, 10.0), 2500.0, 1.0, 0.0),
+            WeatherKind::Snow => (0.85, 120.0, 240.0, Vec3::new(4.0, 0.0, 2.0), 1500.0, 0.8, 1.0),
+        };
+        WeatherState { coverage, cloud_bottom, cloud_top, wind, visibility, precipitation, snow }
+    }
+
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|&k| k == self).unwrap();
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WeatherKind::Clear => "clear",
+            WeatherKind::Fair => "fair",
+            WeatherKind::Cloudy => "cloudy",
+            WeatherKind::Overcast => "overcast",
+            WeatherKind::Fog => "fog",
+            WeatherKind::Storm => "storm",
+            WeatherKind::Snow => "snow",
+        }
+    }
+
+    // Weighted pick for the weather to change to, from a random number
+    fn pick(roll: u64) -> Self {
+        match roll % 100 {
+            0..=19 => WeatherKind::Clear,
+            20..=49 => WeatherKind::Fair,
+            50..=69 => WeatherKind::Cloudy,
+            70..=81 => WeatherKind::Overcast,
+            82..=89 => WeatherKind::Fog,
+            90..=95 => WeatherKind::Storm,
+            _ => WeatherKind::Snow,
+        }
+    }
+}
+
+impl FromStr for WeatherKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Self::ALL.into_iter().find(|k| k.name() == s).ok_or(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WeatherState {
+    // 0 is a clear sky, 1 solid overcast
+    pub coverage: f32,
+    pub cloud_bottom: f32,
+    pub cloud_top: f32,
+    // Units per second
+    pub wind: Vec3,
+    // Distance at which fog hides things at sea level
+    pub visibility: f32,
+    // 0 is dry, 1 a downpour
+    pub precipitation: f32,
+    // 0 when it falls as rain, 1 as snow
+    pub snow: f32,
+}
+
+impl WeatherState {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        Self {
+            coverage: mix(self.coverage, other.coverage),
+            cloud_bottom: mix(self.cloud_bottom, other.cloud_bottom),
+            cloud_top: mix(self.cloud_top, other.cloud_top),
+            wind: self.wind.lerp(other.wind, t),
+            // Blend the fog's density, so it rolls in rather than snapping
+            visibility: 1.0 / mix(1.0 / self.visibility, 1.0 / other.visibility),
+            precipitation: mix(self.precipitation, other.precipitation),
+            snow: mix(self.snow, other.snow),
+        }
+    }
+
+    // How much the cloud layer reads as one solid grey sheet, 0..1
+    pub fn overcast(&self) -> f32 {
+        let t = ((self.coverage - 0.5) / 0.5).clamp(0.0, 1.0);
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    // Fog extinction per unit of distance at sea level
+    pub fn fog_density(&self) -> f32 {
+        (3.0 / self.visibility - 3.0 / CLEAR_VISIBILITY).max(0.0)
+    }
+}
+
+// The current weather, easing towards the kind it's changing to. Either
+// fixed from the settings or the keyboard, or changing by itself every few
+// minutes.
+pub struct Weather {
+    pub current: WeatherState,
+    target: WeatherKind,
+    pub auto: bool,
+    timer: f32,
+    changes: i32,
+    cloud_offset: Vec3,
+    // How soaked the city is, 0..1; lags behind the rain
+    pub wetness: f32,
+}
+
+impl Weather {
+    // `None` lets the weather change by itself
+    pub fn new(kind: Option<WeatherKind>) -> Self {
+        let target = kind.unwrap_or(WeatherKind::Fair);
+        Self { current: target.state(), target, auto: kind.is_none(), timer: 0.0, changes: 0, cloud_offset: Vec3::ZERO, wetness: 0.0 }
+    }
+
+    pub fn kind(&self) -> WeatherKind {
+        self.target
+    }
+
+    // Changes to `kind` and stays there
+    pub fn set(&mut self, kind: WeatherKind) {
+        self.target = kind;
+        self.auto = false;
+    }
+
+    pub fn cycle(&mut self) {
+        self.set(self.target.next());
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        if self.auto {
+            self.timer += dt;
+            if self.timer >= WEATHER_PERIOD {
+                self.timer = 0.0;
+                self.changes += 1;
+                self.target = WeatherKind::pick(hash(self.changes, 0x5eed));
+            }
+        }
+        let blend = 1.0 - (-dt / TRANSITION_TIME).exp();
+        self.current = self.current.lerp(&self.target.state(), blend);
+        // Carried by the same wind the plane feels at that height
+        let cloud_height = (self.current.cloud_bottom + self.current.cloud_top) * 0.5;
+        self.cloud_offset += ambient_wind(cloud_height, &self.current) * dt;
+
+        let rain = self.current.precipitation * (1.0 - self.current.snow);
+        let time = if rain > self.wetness { WETTING_TIME } else { DRYING_TIME };
+        self.wetness += (rain - self.wetness) * (1.0 - (-dt / time).exp());
+    }
+
+    pub fn clouds(&self) -> CloudLayer {
+        CloudLayer {
+            bottom: self.current.cloud_bottom,
+            top: self.current.cloud_top,
+            coverage: self.current.coverage,
+            offset: self.cloud_offset,
+        }
+    }
+
+    // How far the fog lets you see horizontally at this height
+    pub fn fog_visibility(&self, height: f32) -> f32 {
+        let density = self.current.fog_density() * (-(height - SEA_LEVEL).max(0.0) / FOG_HEIGHT).exp();
+        if density <= 0.0 {
+            return f32::INFINITY;
+        }
+        3.0 / density
+    }
+
+    // Cloud layer and fog uniforms, for the sky and scene passes
+    pub unsafe fn apply(&self, program: &ShaderProgram) {
+        self.clouds().apply(program);
+        // The shader measures fog heights from y = 0 rather than the sea
+        program.set_f32("uFogDensity", self.current.fog_density() * (SEA_LEVEL / FOG_HEIGHT).exp());
+        program.set_f32("uFogHeight", FOG_HEIGHT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eases_towards_the_new_weather() {
+        let mut weather = Weather::new(Some(WeatherKind::Clear));
+        weather.set(WeatherKind::Storm);
+        let (clear, storm) = (WeatherKind::Clear.state(), WeatherKind::Storm.state());
+        let mut last = weather.current.coverage;
+        for _ in 0..60 {
+            weather.update(0.5);
+            assert!(weather.current.coverage > last);
+            assert!(weather.current.coverage < storm.coverage);
+            last = weather.current.coverage;
+        }
+        assert!(weather.current.coverage > clear.coverage + (storm.coverage - clear.coverage) * 0.5);
+        assert!(weather.current.precipitation > 0.5);
+    }
+
+    #[test]
+    fn fog_thins_with_height() {
+        let weather = Weather::new(Some(WeatherKind::Fog));
+        let ground = weather.fog_visibility(SEA_LEVEL);
+        assert!((ground - 300.0).abs() < 10.0);
+        assert!(weather.fog_visibility(100.0) > ground * 10.0);
+        assert_eq!(Weather::new(Some(WeatherKind::Fair)).fog_visibility(0.0), f32::INFINITY);
+    }
+
+    #[test]
+    fn soaks_quickly_and_dries_slowly() {
+        let mut weather = Weather::new(Some(WeatherKind::Storm));
+        for _ in 0..60 {
+            weather.update(0.5);
+        }
+        let soaked = weather.wetness;
+        assert!(soaked > 0.8 && soaked <= 1.0, "only {} wet after half a minute of storm", soaked);
+
+        // The same half minute of sunshine leaves most of it
+        weather.set(WeatherKind::Clear);
+        weather.current = WeatherKind::Clear.state();
+        for _ in 0..60 {
+            weather.update(0.5);
+        }
+        assert!(weather.wetness < soaked && weather.wetness > soaked * 0.6);
+
+        let mut snow = Weather::new(Some(WeatherKind::Snow));
+        snow.update(30.0);
+        assert_eq!(snow.wetness, 0.0);
+    }
+}
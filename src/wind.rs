@@ -0,0 +1,137 @@
+use glam::{Vec2, Vec3};
+
+use crate::clouds::noise;
+use crate::weather::WeatherState;
+use crate::terrain::SEA_LEVEL;
+use crate::archetypes::LOT_WIDTH;
+use crate::world::{Building, GRID_SPACING};
+
+// Wind speed grows with height above the sea as a power law
+const PROFILE_EXPONENT: f32 = 1.0 / 7.0;
+// Gust strength relative to the mean wind, from dry weather to a downpour
+const CALM_GUSTINESS: f32 = 0.2;
+const STORM_GUSTINESS: f32 = 0.6;
+// Size of a gust in units, and how many seconds one takes to change
+const GUST_SCALE: f32 = 80.0;
+const GUST_PERIOD: f32 = 4.0;
+// Towers leave a sheltered, turbulent wake this long behind them
+const WAKE_LENGTH: f32 = 40.0;
+// Share of the wind a tower blocks right behind it
+const WAKE_SHELTER: f32 = 0.7;
+// Wake eddies relative to the mean wind speed
+const WAKE_EDDIES: f32 = 0.8;
+// Mean wind speed at which a wake buffets the plane at full strength
+const WAKE_TURBULENT_SPEED: f32 = 15.0;
+
+pub struct Wind {
+    pub velocity: Vec3,
+    // How hard the air buffets the plane, 0..1
+    pub turbulence: f32,
+}
+
+// Mean wind at a height. The weather's wind is what blows through the middle
+// of the cloud layer, which is what carries the clouds.
+pub fn ambient_wind(height: f32, weather: &WeatherState) -> Vec3 {
+    let reference = (weather.cloud_bottom + weather.cloud_top) * 0.5 - SEA_LEVEL;
+    let above_sea = (height - SEA_LEVEL).max(1.0);
+    weather.wind * (above_sea / reference).powf(PROFILE_EXPONENT)
+}
+
+// Gusts carried along with the wind, mostly stronger or weaker blows and
+// partly sideways
+fn gust(p: Vec3, mean: Vec3, weather: &WeatherState, time: f32) -> Vec3 {
+    let gustiness = CALM_GUSTINESS + (STORM_GUSTINESS - CALM_GUSTINESS) * weather.precipitation;
+    let q = (p - mean * time) / GUST_SCALE + Vec3::new(0.0, time / GUST_PERIOD, 0.0);
+    let along = noise(q) * 2.0 - 1.0;
+    let across = noise(q + Vec3::new(17.1, 3.7, 9.2)) * 2.0 - 1.0;
+    let side = Vec3::new(-mean.z, 0.0, mean.x);
+    (mean * along + side * across * 0.5) * gustiness
+}
+
+// How deep p is in the lee of a tower, 0..1. `building_at` is as for
+// check_collision.
+fn wake<'a>(p: Vec3, mean: Vec3, building_at: impl Fn(i32, i32) -> Option<&'a Building>) -> f32 {
+    let dir = Vec2::new(mean.x, mean.z);
+    if dir.length() < 0.1 {
+        return 0.0;
+    }
+    let dir = dir.normalize();
+    let reach = ((LOT_WIDTH * 0.5 + WAKE_LENGTH) / GRID_SPACING).ceil() as i32;
+    let cx = (p.x / GRID_SPACING).round() as i32;
+    let cz = (p.z / GRID_SPACING).round() as i32;
+
+    let mut strength: f32 = 0.0;
+    for z in cz - reach..=cz + reach {
+        for x in cx - reach..=cx + reach {
+            let Some(building) = building_at(x, z) else { continue };
+            let bounds = building.bounds();
+            // Fades out over the first 10 units above the roof
+            let vertical = ((bounds.max.y - p.y) / 10.0 + 1.0).clamp(0.0, 1.0);
+            if vertical <= 0.0 {
+                continue;
+            }
+            // Half the footprint's extent along and across the wind
+            let extent = Vec2::new(bounds.max.x - bounds.min.x, bounds.max.z - bounds.min.z) * 0.5;
+            let half_along = extent.x * dir.x.abs() + extent.y * dir.y.abs();
+            let half_across = extent.x * dir.y.abs() + extent.y * dir.x.abs();
+            let center = bounds.center();
+            let rel = Vec2::new(p.x - center.x, p.z - center.z);
+            let along = rel.dot(dir) - half_along;
+            if !(0.0..=WAKE_LENGTH).contains(&along) {
+                continue;
+            }
+            // The wake spreads out as it trails off
+            let t = along / WAKE_LENGTH;
+            let width = half_across * (1.0 + t * 1.5);
+            let across = rel.perp_dot(dir).abs();
+            if across < width {
+                strength = strength.max((1.0 - t) * (1.0 - across / width) * vertical);
+            }
+        }
+    }
+    strength
+}
+
+// The air's velocity at p, including gusts and the wakes of nearby towers
+pub fn wind_at<'a>(p: Vec3, weather: &WeatherState, time: f32, building_at: impl Fn(i32, i32) -> Option<&'a Building>) -> Wind {
+    let mean = ambient_wind(p.y, weather);
+    let wake = wake(p, mean, building_at);
+    let speed = mean.length();
+
+    let q = p * 0.3 + Vec3::splat(time * 3.0);
+    let eddy = Vec3::new(noise(q), noise(q + Vec3::splat(17.1)), noise(q + Vec3::splat(31.7))) * 2.0 - Vec3::ONE;
+    let velocity = (mean + gust(p, mean, weather, time)) * (1.0 - WAKE_SHELTER * wake) + eddy * speed * WAKE_EDDIES * wake;
+
+    Wind { velocity, turbulence: wake * (speed / WAKE_TURBULENT_SPEED).min(1.0) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::{cell_chunk, ChunkData};
+    use crate::weather::WeatherKind;
+
+    #[test]
+    fn stronger_aloft() {
+        let weather = WeatherKind::Storm.state();
+        let low = ambient_wind(SEA_LEVEL + 5.0, &weather).length();
+        let cloud = ambient_wind((weather.cloud_bottom + weather.cloud_top) * 0.5, &weather);
+        assert!(low < cloud.length() * 0.7);
+        assert!((cloud - weather.wind).length() < 1e-3);
+    }
+
+    #[test]
+    fn towers_shelter_their_lee_side() {
+        let weather = WeatherState { wind: Vec3::new(10.0, 0.0, 0.0), precipitation: 0.0, ..WeatherKind::Fair.state() };
+        let tower = (0..100).filter_map(|x| Building::at(x, 3)).find(|b| b.height > 30.0).unwrap();
+        let chunk = ChunkData::generate(cell_chunk(tower.x, tower.z));
+        let building_at = |x, z| chunk.building_at(x, z);
+        let bounds = tower.bounds();
+        let tower = Vec3::new(bounds.center().x, bounds.min.y + 10.0, bounds.center().z);
+        let lee = Vec3::new(bounds.max.x + 2.0, tower.y, tower.z);
+        assert!(wake(lee, weather.wind, building_at) > 0.5);
+        assert!(wind_at(lee, &weather, 0.0, building_at).turbulence > 0.0);
+        // Well above every roof the wind blows freely
+        assert_eq!(wake(Vec3::new(lee.x, tower.y + 1000.0, lee.z), weather.wind, building_at), 0.0);
+    }
+}
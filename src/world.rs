@@ -1,9 +1,22 @@
-use glam;
+use crate::archetypes::{bridge_parts, building_parts, park_parts, ramp_parts, Part, LAWN_COLOR, LAWN_HEIGHT, LOT_WIDTH};
+use crate::culling::Aabb;
+use crate::districts::{district_at, District};
+use crate::terrain::{height_at, SEA_LEVEL};
 
 pub const GRID_SPACING: f32 = 12.0;
 pub const BUILDING_WIDTH: f32 = 5.0;
-pub const GROUND_LEVEL: f32 = -10.0;
-pub const MAX_BUILDING_HEIGHT: f32 = 60.0;
+// Including spires and antennas
+pub const MAX_BUILDING_HEIGHT: f32 = 270.0;
+// City blocks are BLOCK_SIZE cells square, with ROAD_WIDTH cells of road on the low sides
+pub const BLOCK_SIZE: i32 = 6;
+pub const ROAD_WIDTH: i32 = 1;
+// Lots this close to the sea or rising more than this across stay empty
+const SHORE_CLEARANCE: f32 = 1.0;
+const MAX_LOT_RISE: f32 = 10.0;
+// Roads cross at most this many cells of water, on decks this high above
+// it; wider water has no crossing
+const MAX_BRIDGE_SPAN: i32 = 12;
+const BRIDGE_CLEARANCE: f32 = 14.0;
 
 // Deterministic random number generator
 pub fn hash(x: i32, z: i32) -> u64 {
@@ -13,51 +26,226 @@ pub fn hash(x: i32, z: i32) -> u64 {
     h ^ (h >> 16)
 }
 
-pub fn get_building_info(x: i32, z: i32) -> Option<(f32, glam::Vec3)> {
-    let block_size = 6;
-    let road_width = 1;
+pub fn is_road(x: i32, z: i32) -> bool {
+    x.rem_euclid(BLOCK_SIZE) < ROAD_WIDTH || z.rem_euclid(BLOCK_SIZE) < ROAD_WIDTH
+}
+
+// Whether the middle of cell (x, z) is under water: the sea, the river or a
+// harbour
+pub fn is_water(x: i32, z: i32) -> bool {
+    height_at(x as f32 * GRID_SPACING, z as f32 * GRID_SPACING) < SEA_LEVEL
+}
+
+// Whether the road through water cell (x, z) reaches dry land both ways
+// along (dx, dz) within MAX_BRIDGE_SPAN cells
+fn spanned(x: i32, z: i32, dx: i32, dz: i32) -> bool {
+    let shore = |sign: i32| (1..=MAX_BRIDGE_SPAN).find(|&i| !is_water(x + sign * i * dx, z + sign * i * dz));
+    matches!((shore(1), shore(-1)), (Some(a), Some(b)) if a + b - 1 <= MAX_BRIDGE_SPAN)
+}
+
+// Which ways a bridge in cell (x, z) runs, along x and along z, if there is
+// one
+fn bridge_at(x: i32, z: i32) -> Option<(bool, bool)> {
+    if !is_road(x, z) || !is_water(x, z) {
+        return None;
+    }
+    let along_x = z.rem_euclid(BLOCK_SIZE) < ROAD_WIDTH && spanned(x, z, 1, 0);
+    let along_z = x.rem_euclid(BLOCK_SIZE) < ROAD_WIDTH && spanned(x, z, 0, 1);
+    (along_x || along_z).then_some((along_x, along_z))
+}
+
+// Directions from dry road cell (x, z) to the bridge decks next to it,
+// along their roads
+fn ramps_at(x: i32, z: i32) -> Vec<(i32, i32)> {
+    if !is_road(x, z) || is_water(x, z) {
+        return Vec::new();
+    }
+    [(1, 0), (-1, 0), (0, 1), (0, -1)].into_iter()
+        .filter(|&(dx, dz)| bridge_at(x + dx, z + dz).is_some_and(|(along_x, along_z)| if dx != 0 { along_x } else { along_z }))
+        .collect()
+}
+
+// Lowest and highest ground under the corners of the lot in cell (x, z)
+fn lot_terrain(x: i32, z: i32) -> (f32, f32) {
+    let (cx, cz, l) = (x as f32 * GRID_SPACING, z as f32 * GRID_SPACING, LOT_WIDTH * 0.5);
+    [(-l, -l), (l, -l), (-l, l), (l, l)]
+        .map(|(dx, dz)| height_at(cx + dx, cz + dz))
+        .into_iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(low, high), y| (low.min(y), high.max(y)))
+}
 
-    let mx = x.rem_euclid(block_size);
-    let mz = z.rem_euclid(block_size);
+// Where everything on the lot in cell (x, z) stands: the lowest ground under
+// it, so nothing hangs out over a slope
+pub fn lot_ground(x: i32, z: i32) -> f32 {
+    lot_terrain(x, z).0
+}
+
+// Off the roads, above the tide line and flat enough to build on
+pub fn is_buildable(x: i32, z: i32) -> bool {
+    if is_road(x, z) {
+        return false;
+    }
+    let (low, high) = lot_terrain(x, z);
+    low > SEA_LEVEL + SHORE_CLEARANCE && high - low < MAX_LOT_RISE
+}
 
-    if mx < road_width || mz < road_width {
+// Height to the main roof and facade colour of the building in cell (x, z),
+// drawn from its district's style
+pub fn get_building_info(x: i32, z: i32) -> Option<(f32, glam::Vec3)> {
+    if !is_buildable(x, z) {
         return None; 
     }
 
+    let style = district_at(x, z).style();
     let h_val = hash(x, z);
     
-    if h_val % 100 < 20 {
+    if (h_val % 100) as f32 >= style.density * 100.0 {
         return None;
     }
 
-    let height = ((h_val % 45) + 10) as f32; 
+    // Mostly low, with the odd tall one
+    let t = ((h_val >> 48) % 1000) as f32 / 1000.0;
+    let height = style.min_height + (style.max_height - style.min_height) * t * t;
 
-    // Pastel
-    let r = 0.5 + ((h_val & 0xFF) as f32 / 255.0) * 0.5;
-    let g = 0.5 + (((h_val >> 8) & 0xFF) as f32 / 255.0) * 0.5;
-    let b = 0.5 + (((h_val >> 16) & 0xFF) as f32 / 255.0) * 0.5;
+    // A material from the district's palette, a little lighter or darker
+    // from building to building
+    let material = style.palette[((h_val >> 8) % style.palette.len() as u64) as usize];
+    let shade = 0.9 + ((h_val >> 16) & 0xFF) as f32 / 255.0 * 0.2;
 
-    Some((height, glam::Vec3::new(r, g, b)))
+    Some((height, glam::Vec3::from(material) * shade))
 }
 
-pub fn check_collision(pos: glam::Vec3) -> bool {
-    if pos.y < GROUND_LEVEL + 1.0 { return true; }
+// Precomputed building placement for one grid cell
+#[derive(Clone, Debug)]
+pub struct Building {
+    pub x: i32,
+    pub z: i32,
+    // To the main roof, not counting spires
+    pub height: f32,
+    pub color: glam::Vec3,
+    // Its shape (see archetypes.rs); drawn and collided with alike
+    pub parts: Vec<Part>,
+    // A span of road over the water, or its approach, rather than something
+    // standing on a lot
+    pub bridge: bool,
+}
+
+impl Building {
+    // Park lots have no building but get one all the same for their lawn
+    // and trees, and so do bridges, so those are drawn and collided with too
+    pub fn at(x: i32, z: i32) -> Option<Self> {
+        if let Some((height, color)) = get_building_info(x, z) {
+            return Some(Self { x, z, height, color, parts: building_parts(x, z, height, color), bridge: false });
+        }
+        if let Some((along_x, along_z)) = bridge_at(x, z) {
+            let parts = bridge_parts(x, z, SEA_LEVEL + BRIDGE_CLEARANCE, along_x, along_z);
+            return Some(Self { x, z, height: BRIDGE_CLEARANCE, color: parts[0].color, parts, bridge: true });
+        }
+        let ramps = ramps_at(x, z);
+        if !ramps.is_empty() && lot_ground(x, z) < SEA_LEVEL + BRIDGE_CLEARANCE {
+            let parts = ramp_parts(x, z, SEA_LEVEL + BRIDGE_CLEARANCE, &ramps);
+            let height = SEA_LEVEL + BRIDGE_CLEARANCE - lot_ground(x, z);
+            return Some(Self { x, z, height, color: parts[0].color, parts, bridge: true });
+        }
+        (is_buildable(x, z) && district_at(x, z) == District::Park)
+            .then(|| Self { x, z, height: LAWN_HEIGHT, color: LAWN_COLOR, parts: park_parts(x, z), bridge: false })
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.parts.iter().map(|p| p.bounds).reduce(|a, b| a.union(&b)).unwrap()
+    }
+
+    pub fn contains_point(&self, p: glam::Vec3) -> bool {
+        self.parts.iter().any(|part| part.contains_point(p))
+    }
+}
+
+// What the player flew into
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Contact {
+    Ground,
+    Water,
+    // A building, a tree or a bridge
+    Structure,
+}
+
+// `building_at` gives the building in a cell, if any: the streamed chunks'
+// copies, rather than ones rebuilt for every check
+pub fn check_collision<'a>(pos: glam::Vec3, building_at: impl Fn(i32, i32) -> Option<&'a Building>) -> Option<Contact> {
+    let ground = height_at(pos.x, pos.z);
+    if pos.y < ground.max(SEA_LEVEL) + 1.0 {
+        return Some(if ground < SEA_LEVEL { Contact::Water } else { Contact::Ground });
+    }
 
     let grid_x = (pos.x / GRID_SPACING).round() as i32;
     let grid_z = (pos.z / GRID_SPACING).round() as i32;
 
-    if let Some((height, _)) = get_building_info(grid_x, grid_z) {
-        let building_x = grid_x as f32 * GRID_SPACING;
-        let building_z = grid_z as f32 * GRID_SPACING;
-        
-        let dx = (pos.x - building_x).abs();
-        let dz = (pos.z - building_z).abs();
-        let half_width = BUILDING_WIDTH / 2.0;
-        let top_y = height + GROUND_LEVEL;
-
-        if dx < half_width && dz < half_width && pos.y < top_y {
-            return true;
+    building_at(grid_x, grid_z).filter(|building| building.contains_point(pos)).map(|_| Contact::Structure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::{cell_chunk, ChunkData};
+    use glam::Vec3;
+
+    // Along the east-west roads where they cross the river near the start
+    fn bridges() -> impl Iterator<Item = Building> {
+        (0..60).flat_map(|x| (-150..150).step_by(BLOCK_SIZE as usize).map(move |z| (x, z)))
+            .filter_map(|(x, z)| Building::at(x, z).filter(|b| b.bridge))
+    }
+
+    #[test]
+    fn roads_bridge_the_water() {
+        let bridges: Vec<Building> = bridges().collect();
+        assert!(bridges.len() > 20, "only {} bridge cells", bridges.len());
+        for bridge in &bridges {
+            assert!(is_road(bridge.x, bridge.z));
+            assert!(get_building_info(bridge.x, bridge.z).is_none());
         }
     }
-    false
+
+    #[test]
+    fn bridges_meet_the_shore() {
+        let deck = SEA_LEVEL + BRIDGE_CLEARANCE;
+        let mut ends = 0;
+        for span in bridges().filter(|b| bridge_at(b.x, b.z).is_some_and(|(along_x, _)| along_x)) {
+            for (dx, dz) in [(1, 0), (-1, 0)] {
+                let (x, z) = (span.x + dx, span.z + dz);
+                if is_water(x, z) || lot_ground(x, z) >= deck {
+                    continue;
+                }
+                ends += 1;
+                let ramp = Building::at(x, z).expect("bridge deck ends in mid-air");
+                assert!(ramp.bridge);
+                let tops: Vec<f32> = ramp.parts.iter().map(|p| p.bounds.max.y).collect();
+                let lowest = tops.iter().copied().fold(f32::INFINITY, f32::min);
+                assert!((tops.iter().copied().fold(f32::MIN, f32::max) - deck).abs() < 1e-3);
+                assert!(lowest - lot_ground(x, z) <= (deck - lot_ground(x, z)) / 3.0);
+                assert!(ramp.parts.iter().all(|p| (p.bounds.min.y - lot_ground(x, z)).abs() < 1e-3));
+            }
+        }
+        assert!(ends > 4, "only {} bridge ends", ends);
+    }
+
+    #[test]
+    fn bridges_can_be_hit_or_flown_under() {
+        let bridge = bridges().find(|b| b.parts.len() == 1).unwrap();
+        let chunk = ChunkData::generate(cell_chunk(bridge.x, bridge.z));
+        let collide = |p: Vec3| check_collision(p, |x, z| chunk.building_at(x, z));
+        let middle = Vec3::new(bridge.x as f32 * GRID_SPACING, 0.0, bridge.z as f32 * GRID_SPACING);
+        let deck = SEA_LEVEL + BRIDGE_CLEARANCE;
+        assert_eq!(collide(middle + Vec3::Y * (deck - 0.5)), Some(Contact::Structure));
+        assert_eq!(collide(middle + Vec3::Y * (SEA_LEVEL + BRIDGE_CLEARANCE * 0.5)), None);
+        assert_eq!(collide(middle + Vec3::Y * (deck + 5.0)), None);
+    }
+
+    #[test]
+    fn water_is_its_own_contact() {
+        let (x, z) = (0..300).flat_map(|x| (0..300).map(move |z| (x, z))).find(|&(x, z)| is_water(x, z) && !is_road(x, z)).unwrap();
+        let wet = Vec3::new(x as f32 * GRID_SPACING, SEA_LEVEL + 0.5, z as f32 * GRID_SPACING);
+        assert_eq!(check_collision(wet, |_, _| None), Some(Contact::Water));
+        let dry = Vec3::new(0.0, height_at(0.0, 0.0) + 0.5, 0.0);
+        assert_eq!(check_collision(dry, |_, _| None), Some(Contact::Ground));
+    }
 }